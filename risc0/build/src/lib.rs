@@ -253,6 +253,24 @@ fn get_env_var(name: &str) -> String {
     env::var(name).unwrap_or_default()
 }
 
+/// Returns the path to the `rustc` of the risc0 toolchain.
+///
+/// `RISC0_RUSTC` takes precedence, which allows building guests on machines
+/// without rustup. Otherwise the `risc0` rustup toolchain is used.
+fn risc0_rustc() -> String {
+    let rustc = get_env_var("RISC0_RUSTC");
+    if !rustc.is_empty() {
+        return rustc;
+    }
+
+    let rustc = sanitized_cmd("rustup")
+        .args(["+risc0", "which", "rustc"])
+        .output()
+        .expect("rustup failed to find risc0 toolchain")
+        .stdout;
+    String::from_utf8(rustc).unwrap().trim().to_string()
+}
+
 /// Build a [Command] with CARGO and RUSTUP_TOOLCHAIN environment variables
 /// removed.
 fn sanitized_cmd(tool: &str) -> Command {
@@ -267,14 +285,7 @@ fn sanitized_cmd(tool: &str) -> Command {
 /// Creates a std::process::Command to execute the given cargo
 /// command in an environment suitable for targeting the zkvm guest.
 pub fn cargo_command(subcmd: &str, rust_flags: &[&str]) -> Command {
-    let rustc = sanitized_cmd("rustup")
-        .args(["+risc0", "which", "rustc"])
-        .output()
-        .expect("rustup failed to find risc0 toolchain")
-        .stdout;
-
-    let rustc = String::from_utf8(rustc).unwrap();
-    let rustc = rustc.trim();
+    let rustc = risc0_rustc();
     println!("Using rustc: {rustc}");
 
     let mut cmd = sanitized_cmd("cargo");
@@ -492,18 +503,37 @@ fn build_guest_package<P>(
 }
 
 fn detect_toolchain(name: &str) {
-    let result = Command::new("rustup")
+    if !get_env_var("RISC0_RUSTC").is_empty() {
+        return;
+    }
+
+    let result = match Command::new("rustup")
         .args(["toolchain", "list", "--verbose"])
+        .env("LC_ALL", "C")
         .stderr(Stdio::inherit())
         .output()
-        .unwrap();
+    {
+        Ok(result) => result,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            eprintln!("rustup could not be found.");
+            eprintln!("Install rustup from https://rustup.rs, or set RISC0_RUSTC to the");
+            eprintln!("path of the rustc executable of the risc0 toolchain.");
+            std::process::exit(-1);
+        }
+        Err(err) => panic!("Failed to run: 'rustup toolchain list --verbose': {err}"),
+    };
     if !result.status.success() {
         eprintln!("Failed to run: 'rustup toolchain list --verbose'");
         std::process::exit(result.status.code().unwrap());
     }
 
     let stdout = String::from_utf8(result.stdout).unwrap();
-    if !stdout.lines().any(|line| line.trim().starts_with(name)) {
+    // Each line is the toolchain name, any annotations such as
+    // `(active, default)`, then its path.
+    if !stdout
+        .lines()
+        .any(|line| line.split_whitespace().next() == Some(name))
+    {
         eprintln!("The 'risc0' toolchain could not be found.");
         eprintln!("To install the risc0 toolchain, use cargo-risczero.");
         eprintln!("For example:");
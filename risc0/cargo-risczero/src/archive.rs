@@ -0,0 +1,330 @@
+// Copyright 2024 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{
+    fs::{self, File},
+    io::{self, BufRead, BufReader, Read, Seek},
+    path::{Component, Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use flate2::bufread::GzDecoder;
+use tar::Archive;
+use xz::read::XzDecoder;
+
+/// Compression and container formats of downloadable toolchain archives.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    TarGz,
+    TarXz,
+    TarZst,
+    Zip,
+}
+
+impl ArchiveFormat {
+    /// Detect the format from the first bytes of the archive.
+    pub fn from_magic(header: &[u8]) -> Option<Self> {
+        if header.starts_with(&[0x1f, 0x8b]) {
+            Some(Self::TarGz)
+        } else if header.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Some(Self::TarXz)
+        } else if header.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Self::TarZst)
+        } else if header.starts_with(b"PK\x03\x04") || header.starts_with(b"PK\x05\x06") {
+            Some(Self::Zip)
+        } else {
+            None
+        }
+    }
+
+    /// Detect the format from the file extension of the archive.
+    pub fn from_name(name: &str) -> Option<Self> {
+        if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(Self::TarGz)
+        } else if name.ends_with(".tar.xz") {
+            Some(Self::TarXz)
+        } else if name.ends_with(".tar.zst") {
+            Some(Self::TarZst)
+        } else if name.ends_with(".zip") {
+            Some(Self::Zip)
+        } else {
+            None
+        }
+    }
+}
+
+/// Unpack the archive at `path` into `dst`.
+///
+/// The format is detected from the contents of the file, falling back to the
+/// extension of `name`.
+pub fn unpack(path: &Path, name: &str, dst: &Path) -> Result<()> {
+    let mut file = File::open(path)?;
+    let mut header = Vec::with_capacity(8);
+    (&mut file).take(8).read_to_end(&mut header)?;
+    file.rewind()?;
+
+    let format = ArchiveFormat::from_magic(&header)
+        .or_else(|| ArchiveFormat::from_name(name))
+        .with_context(|| format!("Unsupported archive format: {name}"))?;
+    match format {
+        ArchiveFormat::Zip => unpack_zip(BufReader::new(file), dst)?,
+        format => unpack_tar(format, BufReader::new(file), dst)?,
+    }
+
+    validate_symlinks(dst)
+}
+
+/// Unpack an archive into `dst` as it is read from `reader`, without storing
+/// the archive itself.
+///
+/// Zip archives cannot be extracted this way, because their index is stored
+/// at the end of the file.
+pub fn unpack_stream(reader: impl Read, name: &str, dst: &Path) -> Result<()> {
+    let mut reader = BufReader::new(reader);
+    let format = ArchiveFormat::from_magic(reader.fill_buf()?)
+        .or_else(|| ArchiveFormat::from_name(name))
+        .with_context(|| format!("Unsupported archive format: {name}"))?;
+    if format == ArchiveFormat::Zip {
+        bail!("{name} is a zip archive, which cannot be extracted while downloading");
+    }
+    unpack_tar(format, reader, dst)?;
+
+    validate_symlinks(dst)
+}
+
+/// Unpack a zip archive into `dst`.
+///
+/// `ZipArchive::extract` writes entries through symlinks created by earlier
+/// entries without checking that they stay inside `dst`, so entries are
+/// extracted one by one instead. Symlinks and paths that leave `dst` are
+/// rejected before anything is written.
+fn unpack_zip(reader: impl Read + Seek, dst: &Path) -> Result<()> {
+    let mut archive = zip::ZipArchive::new(reader)?;
+    for index in 0..archive.len() {
+        let mut entry = archive.by_index(index)?;
+        let Some(path) = entry.enclosed_name() else {
+            bail!(
+                "Toolchain archive contains a path outside of the archive: {}",
+                entry.name()
+            );
+        };
+        if entry
+            .unix_mode()
+            .is_some_and(|mode| mode & S_IFMT == S_IFLNK)
+        {
+            bail!(
+                "Toolchain zip archive contains a symlink, which is not supported: {}",
+                entry.name()
+            );
+        }
+
+        let out_path = dst.join(path);
+        if entry.is_dir() {
+            fs::create_dir_all(&out_path)?;
+            continue;
+        }
+        if let Some(parent) = out_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        io::copy(&mut entry, &mut File::create(&out_path)?)?;
+        #[cfg(unix)]
+        if let Some(mode) = entry.unix_mode() {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&out_path, fs::Permissions::from_mode(mode & 0o7777))?;
+        }
+    }
+    Ok(())
+}
+
+/// File type bits of a Unix mode, and the type of a symlink.
+const S_IFMT: u32 = 0o170000;
+const S_IFLNK: u32 = 0o120000;
+
+fn unpack_tar(format: ArchiveFormat, reader: impl BufRead, dst: &Path) -> Result<()> {
+    match format {
+        ArchiveFormat::TarGz => Archive::new(GzDecoder::new(reader)).unpack(dst)?,
+        ArchiveFormat::TarXz => Archive::new(XzDecoder::new(reader)).unpack(dst)?,
+        ArchiveFormat::TarZst => Archive::new(zstd::Decoder::with_buffer(reader)?).unpack(dst)?,
+        ArchiveFormat::Zip => unreachable!("zip archives are not tar archives"),
+    }
+    Ok(())
+}
+
+/// Check that every symlink under `root` points to a location inside it.
+///
+/// Toolchain archives contain relative symlinks (e.g. `clang -> clang-17`),
+/// which are extracted as-is. Hard links are already confined to the
+/// destination by `tar`, but symlink targets are not checked. Absolute targets
+/// are rejected, since even one into `root` would dangle once the toolchain is
+/// moved out of the staging directory.
+fn validate_symlinks(root: &Path) -> Result<()> {
+    let root = fs::canonicalize(root)?;
+    check_symlinks(&root, &root)
+}
+
+fn check_symlinks(root: &Path, dir: &Path) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let file_type = fs::symlink_metadata(&path)?.file_type();
+        if file_type.is_dir() {
+            check_symlinks(root, &path)?;
+        } else if file_type.is_symlink() {
+            let target = fs::read_link(&path)?;
+            if target.has_root() || !resolve(&dir.join(&target))?.starts_with(root) {
+                bail!(
+                    "Toolchain archive contains a symlink that escapes {}: {} -> {}",
+                    root.display(),
+                    path.display(),
+                    target.display()
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Resolve `path` the way the file system would, following symlinks along
+/// the way.
+///
+/// The longest prefix of `path` that exists is canonicalized, and the rest,
+/// e.g. the missing target of a dangling link, is applied lexically.
+fn resolve(path: &Path) -> Result<PathBuf> {
+    for existing in path.ancestors() {
+        let Ok(mut resolved) = fs::canonicalize(existing) else {
+            continue;
+        };
+        for component in path.strip_prefix(existing)?.components() {
+            match component {
+                Component::ParentDir => {
+                    resolved.pop();
+                }
+                Component::Normal(part) => resolved.push(part),
+                _ => {}
+            }
+        }
+        return Ok(resolved);
+    }
+    bail!("Could not resolve {}", path.display())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Write};
+
+    use super::{unpack_zip, validate_symlinks, ArchiveFormat};
+
+    #[test]
+    fn detect_format() {
+        let cases = [
+            (&b"\x1f\x8b\x08\x00"[..], ArchiveFormat::TarGz),
+            (b"\xfd7zXZ\x00\x00\x04", ArchiveFormat::TarXz),
+            (b"\x28\xb5\x2f\xfd\x04\x58", ArchiveFormat::TarZst),
+            (b"PK\x03\x04\x14\x00", ArchiveFormat::Zip),
+        ];
+        for (header, format) in cases {
+            assert_eq!(ArchiveFormat::from_magic(header), Some(format));
+        }
+        assert_eq!(ArchiveFormat::from_magic(b"ustar"), None);
+
+        assert_eq!(
+            ArchiveFormat::from_name("rust-toolchain-x86_64-unknown-linux-gnu.tar.gz"),
+            Some(ArchiveFormat::TarGz)
+        );
+        assert_eq!(
+            ArchiveFormat::from_name("riscv32im-osx-arm64.tar.xz"),
+            Some(ArchiveFormat::TarXz)
+        );
+        assert_eq!(
+            ArchiveFormat::from_name("rust-toolchain-x86_64-pc-windows-msvc.zip"),
+            Some(ArchiveFormat::Zip)
+        );
+        assert_eq!(ArchiveFormat::from_name("toolchain.tar"), None);
+    }
+
+    #[test]
+    fn reject_unsafe_zip_entries() {
+        use zip::write::SimpleFileOptions;
+
+        type Writer = zip::ZipWriter<Cursor<Vec<u8>>>;
+        let zip = |build: &dyn Fn(&mut Writer)| {
+            let mut writer = Writer::new(Cursor::new(vec![]));
+            build(&mut writer);
+            writer.finish().unwrap()
+        };
+        let options = SimpleFileOptions::default();
+
+        let tmp = tempfile::tempdir().unwrap();
+        let dst = tmp.path().join("toolchain");
+        let archive = zip(&|writer| {
+            writer.start_file("bin/rustc", options).unwrap();
+            writer.write_all(b"rustc").unwrap();
+        });
+        unpack_zip(archive, &dst).unwrap();
+        assert_eq!(std::fs::read(dst.join("bin/rustc")).unwrap(), b"rustc");
+
+        let archive = zip(&|writer| {
+            writer.add_symlink("lib", "/etc", options).unwrap();
+            writer.start_file("lib/passwd", options).unwrap();
+        });
+        let dst = tmp.path().join("symlink");
+        assert!(unpack_zip(archive, &dst).is_err());
+        assert!(!dst.join("lib").exists());
+
+        let archive = zip(&|writer| {
+            writer.start_file("../outside", options).unwrap();
+        });
+        let dst = tmp.path().join("parent");
+        assert!(unpack_zip(archive, &dst).is_err());
+        assert!(!tmp.path().join("outside").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn reject_escaping_symlinks() {
+        use std::os::unix::fs::symlink;
+
+        let check = |target: &str| {
+            let tmp = tempfile::tempdir().unwrap();
+            let root = tmp.path().join("toolchain");
+            let bin = root.join("bin");
+            std::fs::create_dir_all(&bin).unwrap();
+            symlink(target, bin.join("link")).unwrap();
+            validate_symlinks(&root)
+        };
+
+        assert!(check("clang-17").is_ok());
+        assert!(check("../lib/libLLVM.so").is_ok());
+        assert!(check("../../outside").is_err());
+        assert!(check("../lib/../../../etc/passwd").is_err());
+        assert!(check("/etc/passwd").is_err());
+
+        // Absolute links are rejected even when they point into the root.
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path().join("toolchain");
+        std::fs::create_dir_all(&root).unwrap();
+        symlink(root.join("file"), root.join("link")).unwrap();
+        assert!(validate_symlinks(&root).is_err());
+
+        // Links are followed when resolving a target: `up` leads back to the
+        // root, so `up/../x` is outside of it.
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path().join("toolchain");
+        let dir = root.join("a/b/c");
+        std::fs::create_dir_all(&dir).unwrap();
+        symlink("../../..", dir.join("up")).unwrap();
+        assert!(validate_symlinks(&root).is_ok());
+        symlink("up/../x", dir.join("escape")).unwrap();
+        assert!(validate_symlinks(&root).is_err());
+    }
+}
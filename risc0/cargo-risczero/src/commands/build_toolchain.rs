@@ -23,7 +23,9 @@ use anyhow::{bail, Context, Result};
 use clap::Parser;
 
 use crate::{
-    toolchain::{RustupToolchain, ToolchainRepo, RUSTUP_TOOLCHAIN_NAME, RUST_BRANCH},
+    toolchain::{
+        lock_toolchains, RustupToolchain, ToolchainRepo, RUSTUP_TOOLCHAIN_NAME, RUST_BRANCH,
+    },
     utils::{ensure_binary, CommandExt},
 };
 
@@ -76,6 +78,7 @@ impl BuildToolchain {
             std::fs::copy(&tool.path(), target_bin_dir.join(tool_name))?;
         }
 
+        let _lock = lock_toolchains()?;
         RustupToolchain::link(RUSTUP_TOOLCHAIN_NAME, &out.toolchain_dir)?;
 
         Ok(())
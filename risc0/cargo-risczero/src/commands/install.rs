@@ -15,34 +15,90 @@
 // This is based on cargo-wasix: https://github.com/wasix-org/cargo-wasix
 
 use std::{
+    fmt::Write as _,
     fs::File,
-    io::BufReader,
+    io::Read,
     path::{Path, PathBuf},
+    process::Command,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
-use anyhow::{bail, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use clap::Parser;
-use downloader::{Download, Downloader};
-use flate2::bufread::GzDecoder;
-use reqwest::{header::HeaderMap, Client};
+use reqwest::{header::HeaderMap, Client, NoProxy, Proxy, StatusCode};
 use reqwest_middleware::{ClientBuilder, ClientWithMiddleware};
 use reqwest_retry::{policies::ExponentialBackoff, RetryTransientMiddleware};
 use serde::Deserialize;
-use tar::Archive;
-use tempfile::tempdir;
-use xz::read::XzDecoder;
 
 use crate::{
-    toolchain::{CppToolchain, RustupToolchain, ToolchainRepo, RUSTUP_TOOLCHAIN_NAME},
-    utils::flock,
+    archive,
+    download::{dns_hint, DownloadConfig, HashingReader, RateLimiter, USER_AGENT},
+    github,
+    manifest::{Manifest, ManifestAsset},
+    toolchain::{
+        lock_toolchains, rustc_path, toolchains_root, CppToolchain, RustupToolchain, ToolchainRepo,
+        UnsupportedTarget, RUSTUP_TOOLCHAIN_NAME,
+    },
+    utils::{format_size, suggest_versions},
 };
-use risc0_build::risc0_data;
 
 /// `cargo risczero install`
 #[derive(Parser)]
 pub struct Install {
     #[arg(long)]
     version: Option<String>,
+
+    /// Release of the C toolchain to install, e.g. `2024.01.05`.
+    ///
+    /// Defaults to the release this version of cargo-risczero is tested
+    /// with, or to the latest release of a `--manifest-url` index.
+    #[arg(long, value_name = "VERSION")]
+    cpp_version: Option<String>,
+
+    /// Ignore the proxy configured in the macOS or Windows system settings.
+    ///
+    /// Proxies set with the `HTTPS_PROXY`, `HTTP_PROXY` or `ALL_PROXY`
+    /// environment variables are still used.
+    #[arg(long)]
+    no_system_proxy: bool,
+
+    /// Extract toolchains while they are downloaded instead of saving the
+    /// archive to a temporary directory first.
+    #[arg(long)]
+    stream: bool,
+
+    /// Download the toolchains for this target triple instead of the host's.
+    ///
+    /// Useful to populate a data directory for another machine, e.g. when
+    /// building a container image for a different architecture. Toolchains
+    /// for a target other than the host are downloaded but not activated.
+    #[arg(long)]
+    target: Option<String>,
+
+    /// Resolve toolchains from a custom release index instead of GitHub.
+    ///
+    /// The index is a JSON document listing the archive URL, size and
+    /// SHA-256 checksum of every release for every target. `file://` paths
+    /// are supported.
+    #[arg(long, value_name = "URL")]
+    manifest_url: Option<String>,
+
+    /// List the releases available to install instead of installing one.
+    ///
+    /// Lists the releases published on GitHub, or those in the
+    /// `--manifest-url` index.
+    #[arg(long)]
+    list_remote: bool,
+
+    /// Abort the install if it takes longer than this many seconds.
+    ///
+    /// The command then exits with status 124. Partially downloaded
+    /// toolchains are removed by the next install.
+    #[arg(long, value_name = "SECONDS")]
+    timeout: Option<u64>,
+
+    #[command(flatten)]
+    download: DownloadConfig,
 }
 
 /// Release returned by Github API.
@@ -52,53 +108,175 @@ struct GithubReleaseData {
     tag_name: String,
 }
 
+/// Error returned by Github API.
+#[derive(Deserialize)]
+struct GithubErrorData {
+    message: String,
+}
+
 /// Release asset returned by Github API.
 #[derive(Deserialize)]
 struct GithubAsset {
     browser_download_url: String,
     name: String,
+    /// Size of the asset in bytes.
+    size: u64,
+}
+
+/// A toolchain archive to download.
+struct ToolchainAsset {
+    name: String,
+    url: String,
+    /// Size of the archive in bytes.
+    size: u64,
+    /// Expected SHA-256 checksum of the archive, hex encoded.
+    sha256: Option<String>,
+}
+
+impl From<GithubAsset> for ToolchainAsset {
+    fn from(asset: GithubAsset) -> Self {
+        Self {
+            name: asset.name,
+            url: asset.browser_download_url,
+            size: asset.size,
+            sha256: None,
+        }
+    }
+}
+
+impl From<&ManifestAsset> for ToolchainAsset {
+    fn from(asset: &ManifestAsset) -> Self {
+        Self {
+            name: asset.file_name().to_string(),
+            url: asset.url.clone(),
+            size: asset.size,
+            sha256: asset.sha256.clone(),
+        }
+    }
 }
 
 const TOKEN_MSG: &str =
     "Setting the GITHUB_TOKEN environment variable is supported to avoid IP throttling by GitHub.";
 
+/// Release of the C toolchain installed from GitHub by default.
+const DEFAULT_CPP_VERSION: &str = "2024.01.05";
+
+/// Estimated ratio between the extracted size of a toolchain and the size of
+/// its compressed archive.
+const EXTRACTED_SIZE_FACTOR: u64 = 3;
+
+/// Directory in the toolchains root that holds in-progress downloads.
+const DOWNLOAD_DIR: &str = "tmp";
+
+/// Name prefix of the staging directories created in [DOWNLOAD_DIR].
+const DOWNLOAD_PREFIX: &str = "download-";
+
+/// Directory in a staging directory that holds the toolchain being replaced.
+const PREVIOUS_DIR: &str = "previous";
+
+/// File in a staging directory that records where [PREVIOUS_DIR] came from.
+const REPLACES_FILE: &str = "replaces";
+
+/// Exit status used when `--timeout` expires, matching `timeout(1)`.
+const TIMEOUT_EXIT_CODE: i32 = 124;
+
 impl Install {
     pub fn run(&self) -> Result<()> {
-        let root_dir = risc0_data()?;
-        let lockfile_path = root_dir.join("rustup-lock");
-        let _lock = flock(&lockfile_path);
+        if let Some(timeout) = self.timeout {
+            // Exiting releases the lock, and the staging directory of an
+            // unfinished download is swept by the next install.
+            std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_secs(timeout));
+                eprintln!("The install did not finish within {timeout}s, aborting.");
+                std::process::exit(TIMEOUT_EXIT_CODE);
+            });
+        }
 
-        let toolchain_dir = root_dir.join("toolchains");
-        let (rust_chain, cpp_chain) = self.install_prebuilt_toolchain(&toolchain_dir)?;
+        if self.list_remote {
+            return self.list_remote();
+        }
 
+        let _lock = lock_toolchains()?;
+
+        // Staging directories are only created while the lock is held, so any
+        // that are left over belong to an install that was interrupted.
+        let root_dir = toolchains_root()?;
+        remove_stale_downloads(&root_dir.join(DOWNLOAD_DIR))?;
+
+        let toolchain_dir = root_dir.join("toolchains");
+        if let Some(target) = self.cross_target() {
+            let (rust_path, cpp_path) = self.download_toolchains(target, &toolchain_dir)?;
+            eprintln!(
+                "Rust Toolchain for {target} downloaded to path {}.",
+                rust_path.display()
+            );
+            eprintln!(
+                "C Toolchain for {target} downloaded to path {}.",
+                cpp_path.display()
+            );
+            eprintln!(
+                "{target} is not the host target, so the toolchains were not activated. \
+                 On a {target} machine, link the Rust toolchain with \
+                 'rustup toolchain link {RUSTUP_TOOLCHAIN_NAME} <path>'."
+            );
+            return Ok(());
+        }
+        let (rust_path, cpp_path) = self.install_prebuilt_toolchain(&toolchain_dir)?;
+        let cpp_chain = CppToolchain::link(&cpp_path)?;
         eprintln!(
-            "Rust Toolchain {} downloaded and installed to path {}.",
-            rust_chain.name,
-            rust_chain.path.display()
-        );
-        eprintln!(
-            "C Toolchain downloaded and installed to path {}.",
+            "C Toolchain {} downloaded and installed to path {}.",
+            toolchain_tag(&cpp_path).unwrap_or_default(),
             cpp_chain.path.display()
         );
-        eprintln!("The risc0 toolchain is now ready to use.");
+
+        let linked = RustupToolchain::rustup_available();
+        if linked {
+            let rust_chain = RustupToolchain::link(RUSTUP_TOOLCHAIN_NAME, &rust_path)?;
+            eprintln!(
+                "Rust Toolchain {} downloaded and installed to path {}.",
+                rust_chain.name,
+                rust_chain.path.display()
+            );
+            // Also link the release under its own name, so that it stays
+            // usable (e.g. `cargo +risc0-<tag>`) after another default is
+            // installed.
+            if let Some(tag) = toolchain_tag(&rust_path) {
+                RustupToolchain::link(&format!("{RUSTUP_TOOLCHAIN_NAME}-{tag}"), &rust_path)?;
+            }
+        } else {
+            eprintln!("Rust Toolchain downloaded to path {}.", rust_path.display());
+        }
+
+        verify_toolchains(&rust_path, &cpp_chain.path, linked)?;
+
+        if linked {
+            eprintln!("The risc0 toolchain is now ready to use.");
+        } else {
+            eprintln!(
+                "rustup was not found, so the Rust toolchain could not be linked as '{RUSTUP_TOOLCHAIN_NAME}'. \
+                 Either install rustup from https://rustup.rs and run this command again, \
+                 or build guests without rustup by setting:\n\n    export RISC0_RUSTC={}\n",
+                rustc_path(&rust_path).display()
+            );
+        }
 
         Ok(())
     }
 
-    /// Tries to download a pre-built toolchain if possible.
+    /// The `--target` triple, if it differs from the host target.
+    fn cross_target(&self) -> Option<&str> {
+        self.target
+            .as_deref()
+            .filter(|target| Some(*target) != guess_host_target())
+    }
+
+    /// Tries to download a pre-built toolchain for the host if possible.
     ///
-    /// Returns the path to the toolchain.
-    fn install_prebuilt_toolchain(
-        &self,
-        toolchain_dir: &Path,
-    ) -> Result<(RustupToolchain, CppToolchain)> {
+    /// Returns the paths to the Rust and C toolchains.
+    fn install_prebuilt_toolchain(&self, toolchain_dir: &Path) -> Result<(PathBuf, PathBuf)> {
         if let Some(target) = guess_host_target() {
             match self.download_toolchains(target, toolchain_dir) {
-                Ok((rust_path, cpp_path)) => {
-                    let rust = RustupToolchain::link(RUSTUP_TOOLCHAIN_NAME, &rust_path)?;
-                    let cpp = CppToolchain::link(&cpp_path)?;
-                    Ok((rust, cpp))
-                }
+                Ok(paths) => Ok(paths),
                 Err(err) => {
                     eprintln!("Could not download pre-built toolchain: {err:?}");
                     Err(err.context("Download of pre-built toolchain failed"))
@@ -109,18 +287,81 @@ impl Install {
         }
     }
 
-    /// Download a pre-built toolchain from Github releases.
+    /// Download the pre-built toolchains from Github releases.
+    ///
+    /// Both toolchains are resolved first, so that the free space needed for
+    /// all of them is checked before any download starts. Unless
+    /// `--max-connections` is 1, the C and Rust toolchains are then downloaded
+    /// and extracted concurrently.
     fn download_toolchains(
         &self,
         target: &str,
         toolchains_root_dir: &Path,
     ) -> Result<(PathBuf, PathBuf)> {
-        let cpp_toolchain_dir =
-            self.download_toolchain(target, toolchains_root_dir, &ToolchainRepo::Cpp)?;
+        let (client, download_client) = self.clients()?;
+        let rt = tokio::runtime::Runtime::new()?;
+        let manifest = self
+            .manifest_url
+            .as_deref()
+            .map(|url| {
+                eprintln!("Getting release info: {url}...");
+                rt.block_on(Manifest::fetch(&client, &self.download, url))
+            })
+            .transpose()?;
+        let cpp_release = rt.block_on(self.get_download_url(
+            &client,
+            manifest.as_ref(),
+            target,
+            &ToolchainRepo::Cpp,
+        ))?;
+        let rust_release = rt.block_on(self.get_download_url(
+            &client,
+            manifest.as_ref(),
+            target,
+            &ToolchainRepo::Rust,
+        ))?;
+
+        let download_dir = toolchains_root()?.join(DOWNLOAD_DIR);
+        std::fs::create_dir_all(&download_dir)?;
+        // Fail early rather than running out of space halfway through.
+        let per_archive = if self.stream {
+            EXTRACTED_SIZE_FACTOR
+        } else {
+            EXTRACTED_SIZE_FACTOR + 1
+        };
+        let required = (cpp_release.1.size + rust_release.1.size) * per_archive;
+        ensure_free_space(&download_dir, required, "download and extract")?;
+
+        let limiter = self.download.rate_limiter();
+        let download = |repo: &ToolchainRepo, (tag_name, asset): (String, ToolchainAsset)| {
+            let toolchain_dir =
+                toolchains_root_dir.join(format!("{}_{target}_{tag_name}", repo.language()));
+            self.download_toolchain(
+                repo,
+                asset,
+                &toolchain_dir,
+                &download_client,
+                limiter.as_ref(),
+            )
+        };
+        let (cpp_toolchain_dir, rust_toolchain_dir) = if self.download.max_connections > 1 {
+            std::thread::scope(|scope| {
+                let cpp = scope.spawn(|| download(&ToolchainRepo::Cpp, cpp_release));
+                let rust = download(&ToolchainRepo::Rust, rust_release);
+                let cpp = cpp
+                    .join()
+                    .unwrap_or_else(|err| std::panic::resume_unwind(err));
+                (cpp, rust)
+            })
+        } else {
+            (
+                download(&ToolchainRepo::Cpp, cpp_release),
+                download(&ToolchainRepo::Rust, rust_release),
+            )
+        };
+        let cpp_toolchain_dir = cpp_toolchain_dir?;
         eprintln!("Downloaded c toolchain to {}", cpp_toolchain_dir.display());
-
-        let rust_toolchain_dir =
-            self.download_toolchain(target, toolchains_root_dir, &ToolchainRepo::Rust)?;
+        let rust_toolchain_dir = rust_toolchain_dir?;
 
         let rust_dir = rust_toolchain_dir.clone();
 
@@ -152,129 +393,472 @@ impl Install {
         Ok((rust_toolchain_dir, cpp_toolchain_dir))
     }
 
-    fn download_toolchain(
-        &self,
-        target: &str,
-        toolchains_root_dir: &Path,
-        repo: &ToolchainRepo,
-    ) -> Result<PathBuf> {
+    /// Build the clients used to query releases and to download archives.
+    fn clients(&self) -> Result<(ClientWithMiddleware, reqwest::blocking::Client)> {
         let mut headers = HeaderMap::new();
 
         // Use api token if specified via env var.
         // Prevents 403 errors when IP is throttled by Github API.
+        // The token is only meant for GitHub, not for custom release indexes.
         let gh_token = std::env::var("GITHUB_TOKEN")
             .ok()
             .map(|x| x.trim().to_string())
-            .filter(|x| !x.is_empty());
+            .filter(|x| !x.is_empty() && self.manifest_url.is_none());
 
         if let Some(token) = gh_token {
             headers.insert("authorization", format!("Bearer {token}").parse()?);
         }
 
-        let client = Client::builder()
+        // By default reqwest falls back to the system proxy settings on macOS
+        // and Windows when no proxy environment variable is set.
+        let proxies = self.no_system_proxy.then(env_proxies).transpose()?;
+
+        let mut client = Client::builder()
+            .default_headers(headers.clone())
+            .user_agent(USER_AGENT)
+            .connect_timeout(self.download.connect_timeout())
+            .timeout(self.download.read_timeout())
+            .local_address(self.download.local_address());
+        let mut download_client = reqwest::blocking::Client::builder()
             .default_headers(headers)
-            .user_agent("cargo-risczero")
-            .build()?;
-        let temp_dir = tempdir()?;
-        let mut downloader = Downloader::builder()
-            .download_folder(temp_dir.path())
-            .build_with_client(client.clone())?;
-
-        let retry_policy = ExponentialBackoff::builder().build_with_max_retries(3);
-        let client = ClientBuilder::new(client)
+            .user_agent(USER_AGENT)
+            .connect_timeout(self.download.connect_timeout())
+            .timeout(self.download.read_timeout())
+            .local_address(self.download.local_address());
+        if let Some(proxies) = proxies {
+            client = client.no_proxy();
+            download_client = download_client.no_proxy();
+            for proxy in proxies {
+                client = client.proxy(proxy.clone());
+                download_client = download_client.proxy(proxy);
+            }
+        }
+
+        let retry_policy =
+            ExponentialBackoff::builder().build_with_max_retries(self.download.retries);
+        let client = ClientBuilder::new(client.build()?)
             .with(RetryTransientMiddleware::new_with_policy(retry_policy))
             .build();
-        let rt = tokio::runtime::Runtime::new()?;
+        Ok((client, download_client.build()?))
+    }
 
-        let (tag_name, download_url) = rt.block_on(self.get_download_url(&client, target, repo))?;
-        let toolchain_dir =
-            toolchains_root_dir.join(format!("{}_{target}_{}", repo.language(), tag_name));
-        if toolchain_dir.is_dir() {
+    /// Download and extract `asset` into `toolchain_dir`.
+    fn download_toolchain(
+        &self,
+        repo: &ToolchainRepo,
+        asset: ToolchainAsset,
+        toolchain_dir: &Path,
+        download_client: &reqwest::blocking::Client,
+        limiter: Option<&RateLimiter>,
+    ) -> Result<PathBuf> {
+        let download_dir = toolchains_root()?.join(DOWNLOAD_DIR);
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let temp_dir = tempfile::Builder::new()
+            .prefix(&format!(
+                "{DOWNLOAD_PREFIX}{}-{timestamp}-",
+                std::process::id()
+            ))
+            .tempdir_in(&download_dir)?;
+
+        if self.stream {
             eprintln!(
-                "Toolchain path {} already exists - deleting existing files!",
-                toolchain_dir.display()
+                "Downloading and extracting {} toolchain ({}) from url '{}'...",
+                repo.language(),
+                format_size(asset.size),
+                &asset.url
+            );
+        } else {
+            eprintln!(
+                "Downloading {} toolchain ({}) from url '{}'...",
+                repo.language(),
+                format_size(asset.size),
+                &asset.url
             );
-            std::fs::remove_dir_all(&toolchain_dir)?;
         }
 
-        // Download.
-        eprintln!(
-            "Downloading {} toolchain from url '{}'...",
-            repo.language(),
-            &download_url
-        );
-
-        let dl = Download::new(&download_url);
-        let results = downloader.download(&[dl])?;
-        for result in results {
-            let summary = result.context(format!("Download failed. {TOKEN_MSG}"))?;
-            let tarball = File::open(summary.file_name)?;
+        let response = self
+            .download
+            .get(download_client, &asset.url)
+            .context(format!("Download failed. {TOKEN_MSG}"))?;
+        let response: Box<dyn Read> = match limiter {
+            Some(limiter) => Box::new(limiter.reader(response)),
+            None => Box::new(response),
+        };
+        let mut response = HashingReader::new(response);
+
+        // Extract into the staging directory and only then move the toolchain
+        // into place, so that an interrupted install never leaves a partial
+        // toolchain behind.
+        let staging_dir = temp_dir.path().join("toolchain");
+        if self.stream {
+            archive::unpack_stream(&mut response, &asset.name, &staging_dir)?;
+            check_digest(&asset, &response.finish()?)?;
+        } else {
+            let archive_path = temp_dir.path().join(&asset.name);
+            std::io::copy(&mut response, &mut File::create(&archive_path)?)
+                .context(format!("Download failed. {TOKEN_MSG}"))?;
+            check_digest(&asset, &response.finish()?)?;
             eprintln!("Extracting...");
-            match repo {
-                ToolchainRepo::Rust => {
-                    let decoder = GzDecoder::new(BufReader::new(tarball));
-                    let mut archive = Archive::new(decoder);
-                    archive.unpack(toolchain_dir.clone())?;
-                }
-                ToolchainRepo::Cpp => {
-                    let decoder = XzDecoder::new(BufReader::new(tarball));
-                    let mut archive = Archive::new(decoder);
-                    archive.unpack(toolchain_dir.clone())?;
-                }
+            archive::unpack(&archive_path, &asset.name, &staging_dir)?;
+        }
+
+        if let Some(parent) = toolchain_dir.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        // An existing toolchain is moved into the staging directory rather
+        // than deleted in place, so that the links to it are never left
+        // pointing at a half-deleted toolchain. It is deleted along with the
+        // staging directory. If the install is interrupted before the new
+        // toolchain is in place, the next install moves it back.
+        let previous_dir = temp_dir.path().join(PREVIOUS_DIR);
+        let replacing = toolchain_dir.is_dir();
+        if replacing {
+            eprintln!(
+                "Toolchain path {} already exists - replacing existing files!",
+                toolchain_dir.display()
+            );
+            std::fs::write(
+                temp_dir.path().join(REPLACES_FILE),
+                toolchain_dir.to_string_lossy().as_bytes(),
+            )?;
+            std::fs::rename(toolchain_dir, &previous_dir)?;
+        }
+        if let Err(err) = std::fs::rename(&staging_dir, toolchain_dir) {
+            if replacing {
+                std::fs::rename(&previous_dir, toolchain_dir)?;
+            }
+            return Err(err).with_context(|| {
+                format!(
+                    "Could not move the toolchain into {}",
+                    toolchain_dir.display()
+                )
+            });
+        }
+        Ok(toolchain_dir.to_path_buf())
+    }
+
+    /// Print the tags of the Rust and C toolchain releases, newest first.
+    fn list_remote(&self) -> Result<()> {
+        let (client, _) = self.clients()?;
+        let rt = tokio::runtime::Runtime::new()?;
+        let manifest = self
+            .manifest_url
+            .as_deref()
+            .map(|url| rt.block_on(Manifest::fetch(&client, &self.download, url)))
+            .transpose()?;
+        for repo in [ToolchainRepo::Rust, ToolchainRepo::Cpp] {
+            let tags = match &manifest {
+                Some(manifest) => manifest
+                    .toolchain(&repo)
+                    .tags()
+                    .map(str::to_string)
+                    .collect(),
+                None => rt.block_on(github::release_tags(
+                    &client,
+                    &self.download,
+                    repo.github_name(),
+                ))?,
+            };
+            println!("{}:", repo.language());
+            for tag in tags {
+                println!("  {tag}");
             }
         }
-        Ok(toolchain_dir)
+        Ok(())
     }
 
+    /// Resolve the release of `repo` to install, from `manifest` when
+    /// `--manifest-url` is set and from GitHub otherwise.
     async fn get_download_url(
         &self,
         client: &ClientWithMiddleware,
+        manifest: Option<&Manifest>,
         target: &str,
         repo: &ToolchainRepo,
-    ) -> Result<(String, String)> {
-        let tag = match repo {
-            ToolchainRepo::Rust => self
-                .version
-                .clone()
-                .map_or("latest".to_string(), |tag| format!("tags/{tag}")),
-            ToolchainRepo::Cpp => "tags/2024.01.05".to_string(),
+    ) -> Result<(String, ToolchainAsset)> {
+        if let Some(manifest) = manifest {
+            let tag = match repo {
+                ToolchainRepo::Rust => self.version.as_deref(),
+                ToolchainRepo::Cpp => self.cpp_version.as_deref(),
+            };
+            let (tag, asset) = manifest.toolchain(repo).find(tag, target)?;
+            return Ok((tag.to_string(), asset.into()));
+        }
+
+        let asset_name = repo.asset_name(target).ok_or_else(|| UnsupportedTarget {
+            language: repo.language(),
+            target: target.to_string(),
+            release: None,
+            available: repo.assets().iter().map(|(target, _)| *target).collect(),
+        })?;
+
+        let version = match repo {
+            ToolchainRepo::Rust => self.version.as_deref(),
+            ToolchainRepo::Cpp => Some(self.cpp_version.as_deref().unwrap_or(DEFAULT_CPP_VERSION)),
         };
+        let tag = version.map_or("latest".to_string(), |tag| format!("tags/{tag}"));
 
-        let repo_name = repo
-            .url()
-            .trim_start_matches("https://github.com/")
-            .trim_end_matches(".git");
+        let repo_name = repo.github_name();
         let release_url = format!("https://api.github.com/repos/{repo_name}/releases/{tag}");
 
         eprintln!("Getting release info: {release_url}...");
 
-        let release: GithubReleaseData = client
-            .get(&release_url)
-            .send()
-            .await?
-            .error_for_status()
-            .context(format!("Could not download release info. {TOKEN_MSG}"))?
-            .json()
-            .await
-            .context("Could not deserialize release info")?;
+        let start = Instant::now();
+        let response = client.get(&release_url).send().await;
+        self.download.trace(&release_url, start, &response);
+        let response = response.map_err(|err| dns_hint(err.into(), &release_url))?;
+        let status = response.status();
+        if !status.is_success() {
+            let err = github_error(response).await;
+            if let (StatusCode::NOT_FOUND, Some(version)) = (status, version) {
+                let tags = github::release_tags(client, &self.download, repo_name)
+                    .await
+                    .unwrap_or_default();
+                return Err(err.context(release_not_found(version, &tags)));
+            }
+            return Err(err.context(format!("Could not download release info. {TOKEN_MSG}")));
+        }
+        let body = response.text().await?;
+        let mut release: GithubReleaseData = serde_json::from_str(&body)
+            .with_context(|| format!("Could not deserialize release info: {}", excerpt(&body)))?;
 
         // Try to find the asset for the wanted target triple.
-        let asset_name = repo.asset_name(target);
-        let asset = release
+        let Some(index) = release
             .assets
             .iter()
-            .find(|asset| asset.name == asset_name)
-            .with_context(|| {
-                format!(
-                    "Release {} does not have a prebuilt toolchain for host {}",
-                    release.tag_name, target
-                )
-            })?;
+            .position(|asset| asset.name == asset_name)
+        else {
+            let available = repo
+                .assets()
+                .iter()
+                .filter(|(_, name)| release.assets.iter().any(|asset| asset.name == *name))
+                .map(|(target, _)| *target)
+                .collect();
+            return Err(UnsupportedTarget {
+                language: repo.language(),
+                target: target.to_string(),
+                release: Some(release.tag_name),
+                available,
+            }
+            .into());
+        };
+        let asset = release.assets.swap_remove(index);
 
-        Ok((release.tag_name, asset.browser_download_url.clone()))
+        Ok((release.tag_name, asset.into()))
     }
 }
 
+/// Describe a failed GitHub API response, including the explanation GitHub
+/// gives in its body and whether the API rate limit has been exhausted.
+async fn github_error(response: reqwest::Response) -> anyhow::Error {
+    let status = response.status();
+    let header = |name| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string)
+    };
+    let remaining = header("x-ratelimit-remaining");
+    let reset = header("x-ratelimit-reset").and_then(|reset| reset.parse::<u64>().ok());
+
+    let body = response.text().await.unwrap_or_default();
+    let message = match serde_json::from_str::<GithubErrorData>(&body) {
+        Ok(error) => error.message,
+        Err(_) => excerpt(&body).to_string(),
+    };
+    let mut err = format!("GitHub API returned {status}: {message}");
+    if remaining.as_deref() == Some("0") {
+        err.push_str(". The API rate limit is exhausted");
+        if let Some(reset) = reset {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |now| now.as_secs());
+            let _ = write!(err, " and resets in {}s", reset.saturating_sub(now));
+        }
+    }
+    anyhow!(err)
+}
+
+/// The error reported when release `version` does not exist, suggesting the
+/// closest releases that do, or else listing the most recent ones.
+fn release_not_found(version: &str, tags: &[String]) -> String {
+    let mut msg = format!("Release {version} was not found.");
+    let suggestions = suggest_versions(version, tags);
+    if !suggestions.is_empty() {
+        let _ = write!(msg, " Did you mean {}?", suggestions.join(", "));
+    } else if !tags.is_empty() {
+        let recent: Vec<_> = tags.iter().take(10).map(String::as_str).collect();
+        let _ = write!(msg, " Recent releases: {}", recent.join(", "));
+    }
+    msg
+}
+
+/// The start of a response body, for error messages.
+fn excerpt(body: &str) -> &str {
+    let end = body.char_indices().nth(500).map_or(body.len(), |(i, _)| i);
+    body[..end].trim()
+}
+
+/// Check the checksum of a downloaded archive against the one listed for it,
+/// if any.
+fn check_digest(asset: &ToolchainAsset, digest: &str) -> Result<()> {
+    match &asset.sha256 {
+        Some(expected) if !expected.eq_ignore_ascii_case(digest) => bail!(
+            "Checksum mismatch for {}: expected sha256 {expected}, got {digest}",
+            asset.url
+        ),
+        _ => Ok(()),
+    }
+}
+
+/// A minimal guest, compiled to check that the Rust toolchain can generate
+/// code for the zkVM.
+const SMOKE_TEST_GUEST: &str = "#![no_std]\npub fn add(a: u32, b: u32) -> u32 {\n    a + b\n}\n";
+
+/// Run quick checks against the installed toolchains and print a pass/fail
+/// line for each, so that a broken install is reported now rather than on the
+/// first guest build.
+fn verify_toolchains(rust_path: &Path, cpp_path: &Path, linked: bool) -> Result<()> {
+    let temp_dir = tempfile::tempdir()?;
+    let guest_src = temp_dir.path().join("guest.rs");
+    std::fs::write(&guest_src, SMOKE_TEST_GUEST)?;
+
+    let mut checks = vec![];
+    if linked {
+        let mut cmd = Command::new("rustc");
+        cmd.args(["+risc0", "--version"]);
+        checks.push(("rustc +risc0 --version", cmd));
+    }
+    let mut cmd = Command::new(rustc_path(rust_path));
+    cmd.args(["--target", "riscv32im-risc0-zkvm-elf"])
+        .args(["--crate-type", "lib", "--emit", "obj", "-o"])
+        .arg(temp_dir.path().join("guest.o"))
+        .arg(&guest_src);
+    checks.push(("rustc --target riscv32im-risc0-zkvm-elf", cmd));
+    let mut cmd = Command::new(cpp_path.join("bin").join("riscv32-unknown-elf-gcc"));
+    cmd.arg("--version");
+    checks.push(("riscv32-unknown-elf-gcc --version", cmd));
+
+    eprintln!("Verifying the installed toolchains:");
+    let mut failed = 0;
+    for (name, mut cmd) in checks {
+        let error = match cmd.output() {
+            Ok(output) if output.status.success() => None,
+            Ok(output) => Some(
+                String::from_utf8_lossy(&output.stderr)
+                    .lines()
+                    .next()
+                    .map_or_else(|| output.status.to_string(), str::to_string),
+            ),
+            Err(err) => Some(err.to_string()),
+        };
+        match error {
+            None => eprintln!("  {name:<42} ok"),
+            Some(err) => {
+                failed += 1;
+                eprintln!("  {name:<42} FAILED: {err}");
+            }
+        }
+    }
+    if failed > 0 {
+        bail!("{failed} toolchain check(s) failed. Try running 'cargo risczero install' again.");
+    }
+    Ok(())
+}
+
+/// The release tag of a toolchain downloaded to `dir`, which is named
+/// `<language>_<target>_<tag>`.
+fn toolchain_tag(dir: &Path) -> Option<&str> {
+    let (_, tag) = dir.file_name()?.to_str()?.rsplit_once('_')?;
+    Some(tag)
+}
+
+/// Remove staging directories left behind by interrupted downloads, first
+/// restoring any toolchain that was moved aside and never replaced.
+fn remove_stale_downloads(dir: &Path) -> Result<()> {
+    if !dir.is_dir() {
+        return Ok(());
+    }
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        if !entry
+            .file_name()
+            .to_string_lossy()
+            .starts_with(DOWNLOAD_PREFIX)
+        {
+            continue;
+        }
+        eprintln!("Removing leftover download {}", entry.path().display());
+        if entry.file_type()?.is_dir() {
+            restore_previous(&entry.path())?;
+            std::fs::remove_dir_all(entry.path())?;
+        } else {
+            std::fs::remove_file(entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+/// Move the toolchain set aside in the staging directory `staging` back to
+/// where it was, if the install that replaced it stopped before the new one
+/// was moved into place.
+fn restore_previous(staging: &Path) -> Result<()> {
+    let previous_dir = staging.join(PREVIOUS_DIR);
+    let Ok(replaced) = std::fs::read_to_string(staging.join(REPLACES_FILE)) else {
+        return Ok(());
+    };
+    let replaced = PathBuf::from(replaced);
+    if previous_dir.is_dir() && std::fs::symlink_metadata(&replaced).is_err() {
+        eprintln!("Restoring {}", replaced.display());
+        std::fs::rename(&previous_dir, &replaced)?;
+    }
+    Ok(())
+}
+
+/// Check that the filesystem containing `path` has at least `required` bytes
+/// available.
+fn ensure_free_space(path: &Path, required: u64, action: &str) -> Result<()> {
+    let available = fs2::available_space(path)
+        .with_context(|| format!("Could not determine free space at {}", path.display()))?;
+    if available < required {
+        bail!(
+            "Not enough disk space to {action} the toolchain at {}: {} required, {} available",
+            path.display(),
+            format_size(required),
+            format_size(available)
+        );
+    }
+    Ok(())
+}
+
+/// Proxies configured through the conventional environment variables.
+fn env_proxies() -> Result<Vec<Proxy>> {
+    let var = |names: [&str; 2]| {
+        names
+            .into_iter()
+            .find_map(|name| std::env::var(name).ok())
+            .filter(|x| !x.trim().is_empty())
+    };
+
+    let mut proxies = vec![];
+    if let Some(url) = var(["https_proxy", "HTTPS_PROXY"]) {
+        proxies.push(Proxy::https(url)?);
+    }
+    if let Some(url) = var(["http_proxy", "HTTP_PROXY"]) {
+        proxies.push(Proxy::http(url)?);
+    }
+    if let Some(url) = var(["all_proxy", "ALL_PROXY"]) {
+        proxies.push(Proxy::all(url)?);
+    }
+
+    let no_proxy = NoProxy::from_env();
+    Ok(proxies
+        .into_iter()
+        .map(|proxy| proxy.no_proxy(no_proxy.clone()))
+        .collect())
+}
+
 /// Try to get the host target triple.
 ///
 /// Only checks for targets that have pre-built toolchains.
@@ -19,4 +19,5 @@ pub mod build_toolchain;
 pub mod deploy;
 pub mod install;
 pub mod new;
+pub mod prune;
 pub mod verify;
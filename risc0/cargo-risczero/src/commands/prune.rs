@@ -0,0 +1,227 @@
+// Copyright 2024 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use anyhow::Result;
+use clap::Parser;
+
+use crate::{
+    toolchain::{
+        lock_toolchains, toolchains_root, CppToolchain, RustupToolchain, RUSTUP_TOOLCHAIN_NAME,
+    },
+    utils::format_size,
+};
+
+/// `cargo risczero prune`
+#[derive(Parser)]
+pub struct Prune {
+    /// Number of versions of each toolchain to keep, most recently installed
+    /// first. The versions linked as the `risc0` rustup toolchain and as the
+    /// active C toolchain are always kept.
+    #[arg(long, default_value_t = 2)]
+    keep: usize,
+
+    /// Print what would be removed without removing anything.
+    #[arg(long)]
+    dry_run: bool,
+}
+
+impl Prune {
+    pub fn run(&self) -> Result<()> {
+        let _lock = lock_toolchains()?;
+
+        let toolchains_dir = toolchains_root()?.join("toolchains");
+        if !toolchains_dir.is_dir() {
+            eprintln!("No toolchains are installed.");
+            return Ok(());
+        }
+        let toolchains_dir = fs::canonicalize(toolchains_dir)?;
+
+        // The default `risc0` toolchain is kept, while the `risc0-<tag>`
+        // aliases of pruned versions are unlinked.
+        let linked: Vec<_> = if RustupToolchain::rustup_available() {
+            RustupToolchain::list()?
+                .into_iter()
+                .filter(|chain| {
+                    chain.name == RUSTUP_TOOLCHAIN_NAME
+                        || chain.name.starts_with(&format!("{RUSTUP_TOOLCHAIN_NAME}-"))
+                })
+                .collect()
+        } else {
+            vec![]
+        };
+        // The active C toolchain is linked into the data directory.
+        let active: Vec<_> = linked
+            .iter()
+            .find(|chain| chain.name == RUSTUP_TOOLCHAIN_NAME)
+            .and_then(|chain| fs::canonicalize(&chain.path).ok())
+            .into_iter()
+            .chain(CppToolchain::linked()?)
+            .collect();
+
+        let mut installed = vec![];
+        for entry in fs::read_dir(&toolchains_dir)? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                installed.push((entry.path(), entry.metadata()?.modified()?));
+            }
+        }
+
+        let (action, done) = if self.dry_run {
+            ("Would remove", "would be reclaimed")
+        } else {
+            ("Removing", "reclaimed")
+        };
+        let stale = stale_toolchains(installed, self.keep, &active);
+        let mut reclaimed = 0;
+        for dir in &stale {
+            let size = dir_size(dir)?;
+            reclaimed += size;
+            eprintln!("{action} {} ({})", dir.display(), format_size(size));
+            if !self.dry_run {
+                fs::remove_dir_all(dir)?;
+            }
+        }
+
+        for chain in linked {
+            let dangling = match fs::canonicalize(&chain.path) {
+                Ok(path) => stale.contains(&path),
+                Err(_) => true,
+            };
+            if dangling {
+                eprintln!(
+                    "{action} rustup toolchain {}, which points to {}",
+                    chain.name,
+                    chain.path.display()
+                );
+                if !self.dry_run {
+                    RustupToolchain::unlink(&chain.name)?;
+                }
+            }
+        }
+
+        eprintln!(
+            "{} toolchain(s) pruned, {} {done}.",
+            stale.len(),
+            format_size(reclaimed)
+        );
+
+        Ok(())
+    }
+}
+
+/// Select the toolchain directories to remove.
+///
+/// Downloaded toolchains are stored as `<language>_<target>_<tag>`. For each
+/// language and target, the `keep` most recently installed versions are kept,
+/// along with the directories containing the `active` toolchains.
+fn stale_toolchains(
+    installed: Vec<(PathBuf, SystemTime)>,
+    keep: usize,
+    active: &[PathBuf],
+) -> Vec<PathBuf> {
+    let mut versions: BTreeMap<String, Vec<(PathBuf, SystemTime)>> = BTreeMap::new();
+    for (path, modified) in installed {
+        let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        let Some((toolchain, _tag)) = name.rsplit_once('_') else {
+            continue;
+        };
+        versions
+            .entry(toolchain.to_string())
+            .or_default()
+            .push((path, modified));
+    }
+
+    versions
+        .into_values()
+        .flat_map(|mut versions| {
+            versions.sort_by_key(|(_, modified)| std::cmp::Reverse(*modified));
+            versions.into_iter().skip(keep).map(|(path, _)| path)
+        })
+        .filter(|path| !active.iter().any(|active| active.starts_with(path)))
+        .collect()
+}
+
+fn dir_size(path: &Path) -> Result<u64> {
+    let mut size = 0;
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        size += if metadata.is_dir() {
+            dir_size(&entry.path())?
+        } else {
+            metadata.len()
+        };
+    }
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        path::PathBuf,
+        time::{Duration, SystemTime},
+    };
+
+    use super::stale_toolchains;
+
+    #[test]
+    fn select_stale_toolchains() {
+        let day = |n: u64| SystemTime::UNIX_EPOCH + Duration::from_secs(n * 86400);
+        let dir = |name: &str| PathBuf::from("/data/toolchains").join(name);
+        let installed = vec![
+            (dir("rust_x86_64-unknown-linux-gnu_r0.1.79.0"), day(3)),
+            (dir("rust_x86_64-unknown-linux-gnu_r0.1.78.0"), day(2)),
+            (dir("rust_x86_64-unknown-linux-gnu_r0.1.77.0"), day(1)),
+            (dir("rust_x86_64-unknown-linux-gnu_r0.1.76.0"), day(0)),
+            (dir("c_x86_64-unknown-linux-gnu_2024.01.05"), day(0)),
+            (dir("unrecognized"), day(0)),
+        ];
+        let active = [
+            dir("rust_x86_64-unknown-linux-gnu_r0.1.76.0"),
+            dir("c_x86_64-unknown-linux-gnu_2024.01.05").join("riscv32im-linux-x86_64"),
+        ];
+
+        assert_eq!(
+            stale_toolchains(installed.clone(), 2, &active),
+            vec![dir("rust_x86_64-unknown-linux-gnu_r0.1.77.0")]
+        );
+        assert_eq!(
+            stale_toolchains(installed.clone(), 0, &active),
+            vec![
+                dir("rust_x86_64-unknown-linux-gnu_r0.1.79.0"),
+                dir("rust_x86_64-unknown-linux-gnu_r0.1.78.0"),
+                dir("rust_x86_64-unknown-linux-gnu_r0.1.77.0"),
+            ]
+        );
+        assert_eq!(
+            stale_toolchains(installed, 0, &[]),
+            vec![
+                dir("c_x86_64-unknown-linux-gnu_2024.01.05"),
+                dir("rust_x86_64-unknown-linux-gnu_r0.1.79.0"),
+                dir("rust_x86_64-unknown-linux-gnu_r0.1.78.0"),
+                dir("rust_x86_64-unknown-linux-gnu_r0.1.77.0"),
+                dir("rust_x86_64-unknown-linux-gnu_r0.1.76.0"),
+            ]
+        );
+    }
+}
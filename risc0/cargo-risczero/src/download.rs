@@ -0,0 +1,356 @@
+// Copyright 2024 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{
+    fmt::{Display, Write as _},
+    fs::OpenOptions,
+    io::{self, Read, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use clap::Args;
+use reqwest::{
+    blocking::{Client, Response},
+    header::HeaderMap,
+    StatusCode,
+};
+use sha2::{Digest, Sha256};
+
+/// The `User-Agent` sent with every request.
+pub const USER_AGENT: &str = concat!("cargo-risczero/", env!("CARGO_PKG_VERSION"));
+
+/// Response headers included in HTTP traces.
+const TRACED_HEADERS: &[&str] = &[
+    "content-length",
+    "retry-after",
+    "x-github-request-id",
+    "x-ratelimit-limit",
+    "x-ratelimit-remaining",
+    "x-ratelimit-reset",
+    "x-ratelimit-used",
+];
+
+/// Network settings for downloading toolchains.
+#[derive(Args, Clone, Debug)]
+pub struct DownloadConfig {
+    /// Limit the combined download rate, in bytes per second.
+    ///
+    /// Accepts `K`, `M` and `G` suffixes, e.g. `500K` or `2M`.
+    #[arg(long, value_name = "RATE", value_parser = parse_rate)]
+    pub limit_rate: Option<u64>,
+
+    /// Number of connections to download with: 2 downloads the C and Rust
+    /// toolchains at the same time, 1 downloads them one after the other.
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 2,
+        value_parser = clap::value_parser!(u8).range(1..=2)
+    )]
+    pub max_connections: u8,
+
+    /// Seconds to wait for a connection to a server to be established.
+    #[arg(long, value_name = "SECONDS", default_value_t = 30)]
+    pub connect_timeout: u64,
+
+    /// Seconds to wait for more data before a stalled request is aborted.
+    #[arg(long, value_name = "SECONDS", default_value_t = 30)]
+    pub read_timeout: u64,
+
+    /// Number of times a request that failed with a transient error is
+    /// retried.
+    #[arg(long, value_name = "N", default_value_t = 3)]
+    pub retries: u32,
+
+    /// Only connect to servers over IPv4, e.g. where IPv6 is broken.
+    #[arg(long, conflicts_with = "ipv6_only")]
+    pub ipv4_only: bool,
+
+    /// Only connect to servers over IPv6.
+    #[arg(long)]
+    pub ipv6_only: bool,
+
+    /// Log the URL, status, timing and rate limit headers of every HTTP
+    /// request to stderr, or append them to FILE with `--trace-http=FILE`.
+    #[arg(long, value_name = "FILE", num_args = 0..=1, require_equals = true)]
+    pub trace_http: Option<Option<PathBuf>>,
+}
+
+impl DownloadConfig {
+    pub fn connect_timeout(&self) -> Duration {
+        Duration::from_secs(self.connect_timeout)
+    }
+
+    pub fn read_timeout(&self) -> Duration {
+        Duration::from_secs(self.read_timeout)
+    }
+
+    /// The local address to bind connections to.
+    ///
+    /// Binding to the unspecified address of one IP version makes the client
+    /// skip server addresses of the other version.
+    pub fn local_address(&self) -> Option<IpAddr> {
+        if self.ipv4_only {
+            Some(Ipv4Addr::UNSPECIFIED.into())
+        } else if self.ipv6_only {
+            Some(Ipv6Addr::UNSPECIFIED.into())
+        } else {
+            None
+        }
+    }
+
+    /// Create the rate limiter to share between all downloads, if a rate
+    /// limit is set.
+    pub fn rate_limiter(&self) -> Option<RateLimiter> {
+        self.limit_rate.map(RateLimiter::new)
+    }
+
+    /// Send a GET request, retrying connection failures, timeouts and server
+    /// errors with exponential backoff.
+    pub fn get(&self, client: &Client, url: &str) -> Result<Response> {
+        let mut attempt = 0;
+        loop {
+            let start = Instant::now();
+            let response = client.get(url).send();
+            self.trace(url, start, &response);
+            match response.and_then(Response::error_for_status) {
+                Ok(response) => return Ok(response),
+                Err(err) if attempt < self.retries && is_transient(&err) => {
+                    attempt += 1;
+                    let delay = Duration::from_secs(1 << attempt.min(5));
+                    eprintln!("{err}, retrying in {}s...", delay.as_secs());
+                    thread::sleep(delay);
+                }
+                Err(err) => return Err(dns_hint(err.into(), url)),
+            }
+        }
+    }
+
+    /// Log the outcome of a GET request to `url` started at `start`, if
+    /// `--trace-http` is set.
+    pub fn trace<R: TracedResponse, E: Display>(
+        &self,
+        url: &str,
+        start: Instant,
+        response: &Result<R, E>,
+    ) {
+        let Some(dest) = &self.trace_http else {
+            return;
+        };
+        let elapsed = start.elapsed();
+        let mut line = format!("GET {url} -> ");
+        match response {
+            Ok(response) => {
+                let _ = write!(line, "{} in {elapsed:.2?}", response.status());
+                for name in TRACED_HEADERS {
+                    if let Some(value) = response.headers().get(*name) {
+                        let _ = write!(line, " {name}={}", value.to_str().unwrap_or("?"));
+                    }
+                }
+            }
+            Err(err) => {
+                let _ = write!(line, "error after {elapsed:.2?}: {err}");
+            }
+        }
+
+        match dest {
+            None => eprintln!("[http] {line}"),
+            Some(path) => {
+                let written = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .and_then(|mut file| writeln!(file, "{line}"));
+                if let Err(err) = written {
+                    eprintln!("Could not write HTTP trace to {}: {err}", path.display());
+                }
+            }
+        }
+    }
+}
+
+/// The parts of a response recorded by [DownloadConfig::trace].
+pub trait TracedResponse {
+    fn status(&self) -> StatusCode;
+    fn headers(&self) -> &HeaderMap;
+}
+
+impl TracedResponse for Response {
+    fn status(&self) -> StatusCode {
+        self.status()
+    }
+
+    fn headers(&self) -> &HeaderMap {
+        self.headers()
+    }
+}
+
+impl TracedResponse for reqwest::Response {
+    fn status(&self) -> StatusCode {
+        self.status()
+    }
+
+    fn headers(&self) -> &HeaderMap {
+        self.headers()
+    }
+}
+
+/// Explain errors caused by a failure to resolve the host name of `url`.
+pub fn dns_hint(err: anyhow::Error, url: &str) -> anyhow::Error {
+    if err
+        .chain()
+        .any(|cause| cause.to_string().starts_with("dns error"))
+    {
+        err.context(format!(
+            "Could not resolve the host name of {url}. Check the network connection and DNS \
+             configuration, or pass --ipv4-only if IPv6 is broken on this network."
+        ))
+    } else {
+        err
+    }
+}
+
+fn is_transient(err: &reqwest::Error) -> bool {
+    err.is_connect()
+        || err.is_timeout()
+        || err.status().is_some_and(|status| {
+            status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
+        })
+}
+
+fn parse_rate(value: &str) -> Result<u64, String> {
+    let (number, multiplier) = if let Some(number) = value.strip_suffix(&['k', 'K'][..]) {
+        (number, 1 << 10)
+    } else if let Some(number) = value.strip_suffix(&['m', 'M'][..]) {
+        (number, 1 << 20)
+    } else if let Some(number) = value.strip_suffix(&['g', 'G'][..]) {
+        (number, 1 << 30)
+    } else {
+        (value, 1)
+    };
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(multiplier))
+        .filter(|&rate| rate > 0)
+        .ok_or_else(|| format!("expected bytes per second, e.g. `500K` or `2M`, got `{value}`"))
+}
+
+/// Limits the combined rate at which data is read through all of the readers
+/// created from it.
+#[derive(Clone)]
+pub struct RateLimiter {
+    rate: u64,
+    /// When the limiter was created, and the number of bytes read since then.
+    state: Arc<Mutex<(Instant, u64)>>,
+}
+
+impl RateLimiter {
+    pub fn new(rate: u64) -> Self {
+        Self {
+            rate,
+            state: Arc::new(Mutex::new((Instant::now(), 0))),
+        }
+    }
+
+    pub fn reader<R: Read>(&self, inner: R) -> Throttled<R> {
+        Throttled {
+            inner,
+            limiter: self.clone(),
+        }
+    }
+
+    /// Account for `bytes` having been read, sleeping until that is within
+    /// the rate limit.
+    fn consume(&self, bytes: usize) {
+        let delay = {
+            let mut state = self.state.lock().unwrap();
+            state.1 += bytes as u64;
+            let due = Duration::from_secs_f64(state.1 as f64 / self.rate as f64);
+            due.saturating_sub(state.0.elapsed())
+        };
+        thread::sleep(delay);
+    }
+}
+
+/// A reader whose rate is limited by a [RateLimiter].
+pub struct Throttled<R> {
+    inner: R,
+    limiter: RateLimiter,
+}
+
+impl<R: Read> Read for Throttled<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // Read at most a tenth of a second's worth at a time, so that the
+        // rate stays smooth.
+        let len = buf.len().min((self.limiter.rate / 10).max(1) as usize);
+        let n = self.inner.read(&mut buf[..len])?;
+        self.limiter.consume(n);
+        Ok(n)
+    }
+}
+
+/// A reader that computes the SHA-256 digest of the data read through it.
+pub struct HashingReader<R> {
+    inner: R,
+    hasher: Sha256,
+}
+
+impl<R: Read> HashingReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+        }
+    }
+
+    /// Read the remaining data and return the hex encoded digest of all of
+    /// it.
+    ///
+    /// Extracting an archive can stop before its end, e.g. at the padding of
+    /// a tar archive, which still needs to be hashed.
+    pub fn finish(mut self) -> io::Result<String> {
+        io::copy(&mut self, &mut io::sink())?;
+        Ok(format!("{:x}", self.hasher.finalize()))
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_rate;
+
+    #[test]
+    fn rate() {
+        assert_eq!(parse_rate("1000"), Ok(1000));
+        assert_eq!(parse_rate("500K"), Ok(500 * 1024));
+        assert_eq!(parse_rate("2m"), Ok(2 * 1024 * 1024));
+        assert_eq!(parse_rate("1G"), Ok(1024 * 1024 * 1024));
+        assert!(parse_rate("0").is_err());
+        assert!(parse_rate("fast").is_err());
+        assert!(parse_rate("K").is_err());
+    }
+}
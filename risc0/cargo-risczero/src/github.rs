@@ -0,0 +1,184 @@
+// Copyright 2024 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Listing the releases of a toolchain repository through the GitHub API.
+
+use std::{
+    path::Path,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use reqwest::header::{HeaderMap, LINK};
+use reqwest_middleware::ClientWithMiddleware;
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinSet;
+
+use crate::{download::DownloadConfig, toolchain::toolchains_root};
+
+/// Directory in the [toolchains_root] where release lists are cached.
+const CACHE_DIR: &str = "cache";
+
+/// How long a cached release list is used before it is fetched again.
+const CACHE_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Number of pages of releases fetched at the same time.
+const CONCURRENT_PAGES: usize = 4;
+
+/// Tag of a release listed by Github API.
+#[derive(Deserialize)]
+struct GithubReleaseTag {
+    tag_name: String,
+}
+
+/// A release list stored in the cache.
+#[derive(Deserialize, Serialize)]
+struct CachedReleases {
+    /// When the list was fetched, in seconds since the Unix epoch.
+    fetched: u64,
+    tags: Vec<String>,
+}
+
+/// List the tags of all releases of `repo_name`, newest first.
+///
+/// The first page of releases tells how many pages there are. The others are
+/// then fetched [CONCURRENT_PAGES] at a time, as far as the remaining API
+/// rate limit allows. The list is cached for an hour.
+pub async fn release_tags(
+    client: &ClientWithMiddleware,
+    download: &DownloadConfig,
+    repo_name: &str,
+) -> Result<Vec<String>> {
+    let cache_path = toolchains_root()?
+        .join(CACHE_DIR)
+        .join(format!("releases-{}.json", repo_name.replace('/', "-")));
+    if let Some(tags) = read_cache(&cache_path) {
+        return Ok(tags);
+    }
+
+    let (mut tags, headers) = fetch_page(client, download, repo_name, 1).await?;
+    let mut last = headers
+        .get(LINK)
+        .and_then(|link| link.to_str().ok())
+        .and_then(last_page)
+        .unwrap_or(1);
+    let remaining = headers
+        .get("x-ratelimit-remaining")
+        .and_then(|remaining| remaining.to_str().ok()?.parse::<usize>().ok());
+    if let Some(remaining) = remaining.filter(|&remaining| remaining < last - 1) {
+        eprintln!(
+            "Listing only the first {} of {last} pages of releases, as the GitHub API rate limit \
+             is almost exhausted.",
+            remaining + 1
+        );
+        last = remaining + 1;
+    }
+
+    let pages: Vec<_> = (2..=last).collect();
+    let mut fetched = vec![];
+    for batch in pages.chunks(CONCURRENT_PAGES) {
+        let mut set = JoinSet::new();
+        for &page in batch {
+            let client = client.clone();
+            let download = download.clone();
+            let repo_name = repo_name.to_string();
+            set.spawn(async move {
+                let (tags, _) = fetch_page(&client, &download, &repo_name, page).await?;
+                anyhow::Ok((page, tags))
+            });
+        }
+        while let Some(result) = set.join_next().await {
+            fetched.push(result??);
+        }
+    }
+    fetched.sort_by_key(|(page, _)| *page);
+    tags.extend(fetched.into_iter().flat_map(|(_, tags)| tags));
+
+    // The cache only saves API requests, so failing to write it is not an
+    // error.
+    let _ = write_cache(&cache_path, &tags);
+    Ok(tags)
+}
+
+/// Fetch one page of releases, returning their tags and the response headers.
+async fn fetch_page(
+    client: &ClientWithMiddleware,
+    download: &DownloadConfig,
+    repo_name: &str,
+    page: usize,
+) -> Result<(Vec<String>, HeaderMap)> {
+    let url = format!("https://api.github.com/repos/{repo_name}/releases?per_page=100&page={page}");
+    let start = Instant::now();
+    let response = client.get(&url).send().await;
+    download.trace(&url, start, &response);
+    let response = response?.error_for_status()?;
+    let headers = response.headers().clone();
+    let releases: Vec<GithubReleaseTag> = response.json().await?;
+    let tags = releases
+        .into_iter()
+        .map(|release| release.tag_name)
+        .collect();
+    Ok((tags, headers))
+}
+
+/// Parse the number of the last page out of a `Link` header.
+fn last_page(link: &str) -> Option<usize> {
+    link.split(',')
+        .find(|part| part.contains(r#"rel="last""#))?
+        .split(['?', '&', '>'])
+        .find_map(|param| param.strip_prefix("page="))?
+        .parse()
+        .ok()
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs())
+}
+
+fn read_cache(path: &Path) -> Option<Vec<String>> {
+    let cached: CachedReleases = serde_json::from_slice(&std::fs::read(path).ok()?).ok()?;
+    (now().saturating_sub(cached.fetched) < CACHE_TTL.as_secs()).then_some(cached.tags)
+}
+
+fn write_cache(path: &Path, tags: &[String]) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let cached = CachedReleases {
+        fetched: now(),
+        tags: tags.to_vec(),
+    };
+    std::fs::write(path, serde_json::to_vec(&cached)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::last_page;
+
+    #[test]
+    fn parse_last_page() {
+        let link = "<https://api.github.com/repositories/1/releases?per_page=100&page=2>; \
+                    rel=\"next\", \
+                    <https://api.github.com/repositories/1/releases?per_page=100&page=4>; \
+                    rel=\"last\"";
+        assert_eq!(last_page(link), Some(4));
+        assert_eq!(
+            last_page("<https://api.github.com/repositories/1/releases?page=1>; rel=\"prev\""),
+            None
+        );
+    }
+}
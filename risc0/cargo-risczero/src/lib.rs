@@ -17,7 +17,11 @@
 #![deny(rustdoc::broken_intra_doc_links)]
 #![cfg_attr(docsrs, feature(doc_cfg, doc_auto_cfg))]
 
+mod archive;
 mod commands;
+mod download;
+mod github;
+mod manifest;
 mod toolchain;
 mod utils;
 
@@ -30,7 +34,7 @@ use clap::{Parser, Subcommand};
 use self::commands::build::BuildCommand;
 use self::commands::{
     build_guest::BuildGuest, build_toolchain::BuildToolchain, deploy::DeployCommand,
-    install::Install, new::NewCommand, verify::VerifyCommand,
+    install::Install, new::NewCommand, prune::Prune, verify::VerifyCommand,
 };
 
 #[derive(Parser)]
@@ -61,6 +65,8 @@ pub enum RisczeroCmd {
     Install(Install),
     /// Creates a new risczero starter project.
     New(NewCommand),
+    /// Remove old versions of the installed toolchains.
+    Prune(Prune),
     /// Uploads the guest code to Bonsai.
     Deploy(DeployCommand),
     /// Verifies if a receipt is valid.
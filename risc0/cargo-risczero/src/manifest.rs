@@ -0,0 +1,280 @@
+// Copyright 2024 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Custom release indexes, used by `cargo risczero install --manifest-url`
+//! instead of the GitHub releases API.
+//!
+//! An index is a JSON document listing, for each toolchain, the release to
+//! install by default and the archive of every release for every target:
+//!
+//! ```json
+//! {
+//!   "rust": {
+//!     "latest": "r0.1.79.0",
+//!     "releases": {
+//!       "r0.1.79.0": {
+//!         "x86_64-unknown-linux-gnu": {
+//!           "url": "https://mirror.example.com/rust-toolchain-x86_64-unknown-linux-gnu.tar.gz",
+//!           "size": 263364935,
+//!           "sha256": "3b5c0a4b1e3bb7e1c5d0a3d4f4e0d7f6b2a1c9e8d7f6a5b4c3d2e1f0a9b8c7d6"
+//!         }
+//!       }
+//!     }
+//!   },
+//!   "cpp": {
+//!     "latest": "2024.01.05",
+//!     "releases": { ... }
+//!   }
+//! }
+//! ```
+//!
+//! `sha256` is optional; when present, downloads are rejected if their
+//! checksum does not match.
+
+use std::{
+    collections::BTreeMap,
+    path::{Component, Path},
+    time::Instant,
+};
+
+use anyhow::{ensure, Context, Result};
+use reqwest_middleware::ClientWithMiddleware;
+use serde::Deserialize;
+
+use crate::{download::DownloadConfig, toolchain::ToolchainRepo, utils::suggest_versions};
+
+/// A custom release index.
+#[derive(Deserialize)]
+pub struct Manifest {
+    rust: ToolchainIndex,
+    cpp: ToolchainIndex,
+}
+
+/// The releases of one toolchain.
+#[derive(Deserialize)]
+pub struct ToolchainIndex {
+    /// Tag of the release installed when no version is requested.
+    latest: String,
+    /// Archives by tag and target triple.
+    releases: BTreeMap<String, BTreeMap<String, ManifestAsset>>,
+}
+
+/// A toolchain archive listed in a [Manifest].
+#[derive(Deserialize)]
+pub struct ManifestAsset {
+    pub url: String,
+    pub size: u64,
+    pub sha256: Option<String>,
+}
+
+impl Manifest {
+    /// Load the index at `url`, which may also be a `file://` path.
+    pub async fn fetch(
+        client: &ClientWithMiddleware,
+        download: &DownloadConfig,
+        url: &str,
+    ) -> Result<Self> {
+        let manifest = match url.strip_prefix("file://") {
+            Some(path) => std::fs::read_to_string(path)
+                .with_context(|| format!("Could not read manifest {path}"))?,
+            None => {
+                let start = Instant::now();
+                let response = client.get(url).send().await;
+                download.trace(url, start, &response);
+                response?
+                    .error_for_status()
+                    .with_context(|| format!("Could not download manifest {url}"))?
+                    .text()
+                    .await?
+            }
+        };
+        Self::parse(&manifest).with_context(|| format!("Invalid manifest {url}"))
+    }
+
+    fn parse(manifest: &str) -> Result<Self> {
+        Ok(serde_json::from_str(manifest)?)
+    }
+
+    pub fn toolchain(&self, repo: &ToolchainRepo) -> &ToolchainIndex {
+        match repo {
+            ToolchainRepo::Rust => &self.rust,
+            ToolchainRepo::Cpp => &self.cpp,
+        }
+    }
+}
+
+impl ToolchainIndex {
+    /// Tags of all releases in the index, in descending order.
+    pub fn tags(&self) -> impl Iterator<Item = &str> {
+        self.releases.keys().rev().map(String::as_str)
+    }
+
+    /// Find the archive of release `tag`, or of the latest release, for
+    /// `target`.
+    ///
+    /// The tag and archive name become part of paths on disk, so they must
+    /// each be a single file name.
+    pub fn find(&self, tag: Option<&str>, target: &str) -> Result<(&str, &ManifestAsset)> {
+        let tag = tag.unwrap_or(&self.latest);
+        let (tag, targets) = self.releases.get_key_value(tag).with_context(|| {
+            let tags: Vec<_> = self.releases.keys().cloned().collect();
+            let suggestions = suggest_versions(tag, &tags);
+            if suggestions.is_empty() {
+                format!(
+                    "Release {tag} is not in the manifest. Available releases: {}",
+                    tags.join(", ")
+                )
+            } else {
+                format!(
+                    "Release {tag} is not in the manifest. Did you mean {}?",
+                    suggestions.join(", ")
+                )
+            }
+        })?;
+        let asset = targets.get(target).with_context(|| {
+            let targets: Vec<_> = targets.keys().map(String::as_str).collect();
+            format!(
+                "Release {tag} has no archive for {target}. Available targets: {}",
+                targets.join(", ")
+            )
+        })?;
+        ensure!(is_file_name(tag), "Release {tag:?} is not a valid tag");
+        ensure!(
+            is_file_name(asset.file_name()),
+            "Release {tag} has an invalid archive URL for {target}: {}",
+            asset.url
+        );
+        Ok((tag, asset))
+    }
+}
+
+/// Whether `name` is a single, ordinary path component.
+fn is_file_name(name: &str) -> bool {
+    let mut components = Path::new(name).components();
+    !name.contains(['/', '\\'])
+        && !name.contains("..")
+        && matches!(components.next(), Some(Component::Normal(_)))
+        && components.next().is_none()
+}
+
+impl ManifestAsset {
+    /// The file name of the archive, taken from its URL.
+    pub fn file_name(&self) -> &str {
+        let path = self.url.split(['?', '#']).next().unwrap_or_default();
+        path.rsplit('/').next().unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Manifest;
+    use crate::toolchain::ToolchainRepo;
+
+    const MANIFEST: &str = r#"{
+        "rust": {
+            "latest": "r0.1.79.0",
+            "releases": {
+                "r0.1.78.0": {
+                    "x86_64-unknown-linux-gnu": {
+                        "url": "https://mirror.example.com/r0.1.78.0/rust.tar.gz",
+                        "size": 100
+                    }
+                },
+                "r0.1.79.0": {
+                    "x86_64-unknown-linux-gnu": {
+                        "url": "https://mirror.example.com/r0.1.79.0/rust.tar.gz?token=1",
+                        "size": 200,
+                        "sha256": "00ff"
+                    }
+                }
+            }
+        },
+        "cpp": { "latest": "2024.01.05", "releases": {} }
+    }"#;
+
+    #[test]
+    fn find_release() {
+        let manifest = Manifest::parse(MANIFEST).unwrap();
+        let rust = manifest.toolchain(&ToolchainRepo::Rust);
+
+        let (tag, asset) = rust.find(None, "x86_64-unknown-linux-gnu").unwrap();
+        assert_eq!(tag, "r0.1.79.0");
+        assert_eq!(asset.file_name(), "rust.tar.gz");
+        assert_eq!(asset.size, 200);
+        assert_eq!(asset.sha256.as_deref(), Some("00ff"));
+
+        let (tag, asset) = rust
+            .find(Some("r0.1.78.0"), "x86_64-unknown-linux-gnu")
+            .unwrap();
+        assert_eq!(tag, "r0.1.78.0");
+        assert_eq!(asset.sha256, None);
+
+        assert!(rust
+            .find(Some("r0.1.77.0"), "x86_64-unknown-linux-gnu")
+            .is_err());
+        assert!(rust.find(None, "aarch64-apple-darwin").is_err());
+        assert!(manifest
+            .toolchain(&ToolchainRepo::Cpp)
+            .find(None, "x86_64-unknown-linux-gnu")
+            .is_err());
+
+        assert_eq!(rust.tags().collect::<Vec<_>>(), ["r0.1.79.0", "r0.1.78.0"]);
+    }
+
+    #[test]
+    fn reject_unsafe_tags() {
+        let manifest = Manifest::parse(
+            r#"{
+                "rust": {
+                    "latest": "../../../home/user",
+                    "releases": {
+                        "../../../home/user": {
+                            "x86_64-unknown-linux-gnu": {
+                                "url": "https://mirror.example.com/rust.tar.gz",
+                                "size": 100
+                            }
+                        },
+                        "..": {
+                            "x86_64-unknown-linux-gnu": {
+                                "url": "https://mirror.example.com/rust.tar.gz",
+                                "size": 100
+                            }
+                        },
+                        "": {
+                            "x86_64-unknown-linux-gnu": {
+                                "url": "https://mirror.example.com/rust.tar.gz",
+                                "size": 100
+                            }
+                        },
+                        "r0.1.79.0": {
+                            "x86_64-unknown-linux-gnu": {
+                                "url": "https://mirror.example.com/..",
+                                "size": 100
+                            }
+                        }
+                    }
+                },
+                "cpp": { "latest": "2024.01.05", "releases": {} }
+            }"#,
+        )
+        .unwrap();
+        let rust = manifest.toolchain(&ToolchainRepo::Rust);
+        let target = "x86_64-unknown-linux-gnu";
+
+        assert!(rust.find(None, target).is_err());
+        assert!(rust.find(Some(".."), target).is_err());
+        assert!(rust.find(Some(""), target).is_err());
+        assert!(rust.find(Some("r0.1.79.0"), target).is_err());
+    }
+}
@@ -15,16 +15,15 @@
 // This is based on cargo-wasix: https://github.com/wasix-org/cargo-wasix
 
 use std::{
-    fs,
+    fmt, fs,
     path::{Path, PathBuf},
-    process::Command,
+    process::{Command, Stdio},
 };
 
 use anyhow::{bail, Context, Result};
-use fs_extra::dir::CopyOptions;
 use risc0_build::risc0_data;
 
-use crate::utils::CommandExt;
+use crate::utils::{flock, CommandExt, FileLock};
 
 pub enum ToolchainRepo {
     Rust,
@@ -39,31 +38,148 @@ impl ToolchainRepo {
         }
     }
 
-    pub const fn language(&self) -> &str {
+    /// The `owner/name` of the GitHub repository the releases are published in.
+    pub fn github_name(&self) -> &str {
+        self.url()
+            .trim_start_matches("https://github.com/")
+            .trim_end_matches(".git")
+    }
+
+    pub const fn language(&self) -> &'static str {
         match self {
             Self::Rust => "rust",
             Self::Cpp => "c",
         }
     }
 
-    pub fn asset_name(&self, target: &str) -> String {
+    /// Prebuilt release assets of this repo, by host target triple.
+    pub const fn assets(&self) -> &'static [(&'static str, &'static str)] {
         match self {
-            Self::Rust => format!("rust-toolchain-{target}.tar.gz"),
-            Self::Cpp => match target {
-                "aarch64-apple-darwin" => "riscv32im-osx-arm64.tar.xz".to_string(),
-                "x86_64-unknown-linux-gnu" => "riscv32im-linux-x86_64.tar.xz".to_string(),
-                _ => panic!("binaries for {target} are not available"),
-            },
+            Self::Rust => RUST_ASSETS,
+            Self::Cpp => CPP_ASSETS,
+        }
+    }
+
+    /// Name of the release asset with the prebuilt toolchain for `target`, if
+    /// there is one.
+    pub fn asset_name(&self, target: &str) -> Option<&'static str> {
+        self.assets()
+            .iter()
+            .find(|(asset_target, _)| *asset_target == target)
+            .map(|(_, asset)| *asset)
+    }
+}
+
+/// Error for a target without a prebuilt toolchain.
+#[derive(Debug)]
+pub struct UnsupportedTarget {
+    /// Language of the toolchain, see [ToolchainRepo::language].
+    pub language: &'static str,
+    pub target: String,
+    /// The release that was searched, if the target has prebuilt toolchains
+    /// in general but not in this release.
+    pub release: Option<String>,
+    /// Targets that do have a prebuilt toolchain.
+    pub available: Vec<&'static str>,
+}
+
+impl fmt::Display for UnsupportedTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.release {
+            Some(release) => write!(
+                f,
+                "Release {release} does not have a prebuilt {} toolchain for {}.",
+                self.language, self.target
+            )?,
+            None => write!(
+                f,
+                "No prebuilt {} toolchain is available for {}.",
+                self.language, self.target
+            )?,
         }
+        if !self.available.is_empty() {
+            write!(f, " Available targets: {}.", self.available.join(", "))?;
+        }
+        write!(
+            f,
+            " You can build the toolchain yourself with 'cargo risczero build-toolchain'."
+        )
     }
 }
 
+impl std::error::Error for UnsupportedTarget {}
+
+/// Release assets of the Rust toolchain, by host target triple.
+const RUST_ASSETS: &[(&str, &str)] = &[
+    (
+        "aarch64-apple-darwin",
+        "rust-toolchain-aarch64-apple-darwin.tar.gz",
+    ),
+    (
+        "x86_64-apple-darwin",
+        "rust-toolchain-x86_64-apple-darwin.tar.gz",
+    ),
+    (
+        "x86_64-pc-windows-msvc",
+        "rust-toolchain-x86_64-pc-windows-msvc.tar.gz",
+    ),
+    (
+        "x86_64-unknown-linux-gnu",
+        "rust-toolchain-x86_64-unknown-linux-gnu.tar.gz",
+    ),
+];
+
+/// Release assets of the C toolchain, by host target triple.
+const CPP_ASSETS: &[(&str, &str)] = &[
+    ("aarch64-apple-darwin", "riscv32im-osx-arm64.tar.xz"),
+    ("x86_64-unknown-linux-gnu", "riscv32im-linux-x86_64.tar.xz"),
+];
+
 /// Branch to use in the custom Rust repo.
 pub const RUST_BRANCH: &str = "risc0";
 
 /// The name of the rustup toolchain
 pub const RUSTUP_TOOLCHAIN_NAME: &str = "risc0";
 
+/// Lock file held while toolchains are modified, see [lock_toolchains].
+const TOOLCHAIN_LOCK_FILE: &str = "rustup-lock";
+
+/// Name of the link in the risc0 data directory to the active C toolchain.
+const CPP_LINK_NAME: &str = "cpp";
+
+/// Environment variable that relocates the downloaded toolchains.
+pub const TOOLCHAIN_DIR_ENV: &str = "RISC0_TOOLCHAIN_DIR";
+
+/// Get the directory that stores the downloaded toolchains, along with the
+/// staging area for downloads and cached release lists.
+///
+/// Toolchains take several GB, so [TOOLCHAIN_DIR_ENV] can place them on a
+/// different disk than the risc0 data directory, which is the default.
+pub fn toolchains_root() -> Result<PathBuf> {
+    match std::env::var_os(TOOLCHAIN_DIR_ENV) {
+        Some(dir) if !dir.is_empty() => Ok(dir.into()),
+        _ => risc0_data(),
+    }
+}
+
+/// Take the locks held while toolchains are modified.
+///
+/// Both the risc0 data directory and the [toolchains_root] are locked, so
+/// that installs which share either one exclude each other. The locks are
+/// taken in a fixed order, so that two installs cannot deadlock.
+pub fn lock_toolchains() -> Result<Vec<FileLock>> {
+    let mut dirs = vec![risc0_data()?, toolchains_root()?];
+    for dir in &mut dirs {
+        fs::create_dir_all(&*dir)?;
+        *dir = fs::canonicalize(&*dir)?;
+    }
+    dirs.sort();
+    dirs.dedup();
+    dirs.iter()
+        .map(|dir| flock(&dir.join(TOOLCHAIN_LOCK_FILE)))
+        .collect()
+}
+
 /// A rustup toolchain manager
 #[derive(Clone, Debug)]
 pub struct RustupToolchain {
@@ -75,27 +191,37 @@ pub struct RustupToolchain {
 }
 
 impl RustupToolchain {
-    /// Verify if the toolchain is present in rustup.
-    ///
-    /// Returns the path to the toolchain.
-    fn find_by_name(name: &str) -> Result<Option<Self>, anyhow::Error> {
+    /// Check whether `rustup` can be run on this machine.
+    pub fn rustup_available() -> bool {
+        Command::new("rustup")
+            .arg("--version")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .is_ok_and(|status| status.success())
+    }
+
+    /// List the toolchains known to rustup.
+    pub fn list() -> Result<Vec<Self>> {
         let out = Command::new("rustup")
             .args(["toolchain", "list", "--verbose"])
+            .env("LC_ALL", "C")
             .capture_stdout()?;
-        let path_raw = out
-            .lines()
-            .find(|line| line.trim().starts_with(name))
-            .and_then(|line| line.strip_prefix(name))
-            .map(|line| line.trim());
-
-        if let Some(path) = path_raw {
-            Ok(Some(Self {
+
+        Ok(parse_toolchains(&out)
+            .into_iter()
+            .map(|(name, path)| Self {
                 name: name.to_string(),
-                path: path.into(),
-            }))
-        } else {
-            Ok(None)
-        }
+                path,
+            })
+            .collect())
+    }
+
+    /// Verify if the toolchain is present in rustup.
+    ///
+    /// Returns the path to the toolchain.
+    fn find_by_name(name: &str) -> Result<Option<Self>, anyhow::Error> {
+        Ok(Self::list()?.into_iter().find(|chain| chain.name == name))
     }
 
     /// Link the toolchain to a local directory via rustup.
@@ -107,12 +233,7 @@ impl RustupToolchain {
         );
 
         // Small sanity check.
-        #[cfg(not(target_os = "windows"))]
-        let rustc_exe = "rustc";
-        #[cfg(target_os = "windows")]
-        let rustc_exe = "rustc.exe";
-
-        let rustc_path = dir.join("bin").join(rustc_exe);
+        let rustc_path = rustc_path(dir);
         if !rustc_path.is_file() {
             bail!(
                 "Invalid toolchain directory: rustc executable not found at {}",
@@ -123,10 +244,7 @@ impl RustupToolchain {
         // If already present, unlink first.
         // This is required because otherwise rustup can get in a buggy state.
         if Self::find_by_name(name)?.is_some() {
-            Command::new("rustup")
-                .args(["toolchain", "remove", name])
-                .run()
-                .context("Could not remove existing toolchain")?;
+            Self::unlink(name)?;
         }
 
         Command::new("rustup")
@@ -142,6 +260,44 @@ impl RustupToolchain {
             path: dir.into(),
         })
     }
+
+    /// Remove the toolchain from rustup, leaving its directory in place.
+    pub fn unlink(name: &str) -> Result<()> {
+        Command::new("rustup")
+            .args(["toolchain", "remove", name])
+            .run()
+            .context("Could not remove existing toolchain")?;
+        Ok(())
+    }
+}
+
+/// Parse the names and paths of the toolchains in the output of
+/// `rustup toolchain list --verbose`.
+///
+/// Each line has the form `<name> [(<annotation>)...] <path>`. Newer versions
+/// of rustup annotate the default and active toolchains, older ones don't.
+fn parse_toolchains(list: &str) -> Vec<(&str, PathBuf)> {
+    list.lines()
+        .filter_map(|line| {
+            let (name, rest) = line.trim().split_once(char::is_whitespace)?;
+            let mut rest = rest.trim_start();
+            while let Some(annotation) = rest.strip_prefix('(') {
+                rest = annotation.split_once(')')?.1.trim_start();
+            }
+            let path = rest.trim_end();
+            (!path.is_empty()).then(|| (name, path.into()))
+        })
+        .collect()
+}
+
+/// The path of the `rustc` executable of the Rust toolchain in `dir`.
+pub fn rustc_path(dir: &Path) -> PathBuf {
+    #[cfg(not(target_os = "windows"))]
+    let rustc_exe = "rustc";
+    #[cfg(target_os = "windows")]
+    let rustc_exe = "rustc.exe";
+
+    dir.join("bin").join(rustc_exe)
 }
 
 /// A rustup toolchain manager
@@ -153,8 +309,7 @@ pub struct CppToolchain {
 
 impl CppToolchain {
     fn get_subdir(path: &Path) -> Result<PathBuf> {
-        let sub_dir: Vec<std::result::Result<std::fs::DirEntry, std::io::Error>> =
-            std::fs::read_dir(path)?.into_iter().collect();
+        let sub_dir = std::fs::read_dir(path)?.collect::<std::io::Result<Vec<_>>>()?;
         if sub_dir.len() != 1 {
             bail!(
                 "Expected {} to only have 1 subdirectory, found {}",
@@ -162,31 +317,127 @@ impl CppToolchain {
                 sub_dir.len()
             );
         }
-        let entry = sub_dir[0].as_ref().unwrap();
-        Ok(entry.path())
+        Ok(sub_dir[0].path())
     }
 
+    /// Make the C toolchain extracted into `path` the active one.
+    ///
+    /// Builds look for the C toolchain in `cpp` in the risc0 data directory,
+    /// which is a symlink to the versioned install directory, so that no copy
+    /// of the toolchain is made.
     pub fn link(path: &Path) -> Result<Self> {
-        let cpp_download_dir = Self::get_subdir(path)?;
-        let r0_data = risc0_data()?;
-        fs_extra::dir::copy(
-            cpp_download_dir.clone(),
-            &r0_data,
-            &CopyOptions::new().overwrite(true).copy_inside(true),
-        )?;
-
-        // for c, we will keep the toolchains in the r0_data directory for now
-        let cpp_install_dir = &r0_data.join("cpp");
-        if cpp_install_dir.exists() {
-            fs::remove_dir_all(cpp_install_dir)?;
+        let cpp_download_dir = fs::canonicalize(Self::get_subdir(path)?)?;
+        let data = risc0_data()?;
+        let cpp_install_dir = data.join(CPP_LINK_NAME);
+        // Build the new link beside the old one and rename it over the top, so
+        // `cpp` always points at a complete toolchain.
+        let new_link = data.join(format!("{CPP_LINK_NAME}.new"));
+        if fs::symlink_metadata(&new_link).is_ok() {
+            remove_link(&new_link)?;
+        }
+        symlink_dir(&cpp_download_dir, &new_link).with_context(|| {
+            format!(
+                "Could not link {} to {}",
+                cpp_install_dir.display(),
+                cpp_download_dir.display()
+            )
+        })?;
+        if fs::rename(&new_link, &cpp_install_dir).is_err() {
+            match fs::symlink_metadata(&cpp_install_dir) {
+                // Earlier versions copied the toolchain here.
+                Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(&cpp_install_dir)?,
+                // Windows cannot rename over a directory symlink.
+                Ok(_) => remove_link(&cpp_install_dir)?,
+                Err(_) => {}
+            }
+            fs::rename(&new_link, &cpp_install_dir)?;
         }
-        fs::rename(
-            r0_data.join(cpp_download_dir.file_name().unwrap()),
-            cpp_install_dir,
-        )?;
 
         Ok(Self {
-            path: cpp_install_dir.into(),
+            path: cpp_install_dir,
         })
     }
+
+    /// The install directory of the active C toolchain, if one is linked.
+    pub fn linked() -> Result<Option<PathBuf>> {
+        Ok(fs::read_link(risc0_data()?.join(CPP_LINK_NAME)).ok())
+    }
+}
+
+#[cfg(unix)]
+fn symlink_dir(original: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(original, link)
+}
+
+#[cfg(windows)]
+fn symlink_dir(original: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::windows::fs::symlink_dir(original, link)
+}
+
+// Directory symlinks are removed as directories on Windows.
+fn remove_link(link: &Path) -> std::io::Result<()> {
+    fs::remove_file(link).or_else(|_| fs::remove_dir(link))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::{parse_toolchains, ToolchainRepo};
+
+    fn parse_toolchain_path(list: &str, name: &str) -> Option<PathBuf> {
+        parse_toolchains(list)
+            .into_iter()
+            .find_map(|(toolchain, path)| (toolchain == name).then_some(path))
+    }
+
+    #[test]
+    fn asset_names() {
+        assert_eq!(
+            ToolchainRepo::Rust.asset_name("x86_64-unknown-linux-gnu"),
+            Some("rust-toolchain-x86_64-unknown-linux-gnu.tar.gz")
+        );
+        assert_eq!(
+            ToolchainRepo::Cpp.asset_name("aarch64-apple-darwin"),
+            Some("riscv32im-osx-arm64.tar.xz")
+        );
+        assert_eq!(
+            ToolchainRepo::Cpp.asset_name("x86_64-pc-windows-msvc"),
+            None
+        );
+        assert_eq!(
+            ToolchainRepo::Rust.asset_name("riscv64gc-unknown-linux-gnu"),
+            None
+        );
+    }
+
+    #[test]
+    fn parse_toolchain_list() {
+        // rustup < 1.28
+        let list = "stable-x86_64-unknown-linux-gnu (default)\t/home/u/.rustup/toolchains/stable\n\
+                    risc0-2024\t/data/toolchains/rust_2024\n\
+                    risc0\t/data/toolchains/rust x86_64\n";
+        assert_eq!(
+            parse_toolchain_path(list, "risc0"),
+            Some(PathBuf::from("/data/toolchains/rust x86_64"))
+        );
+
+        // rustup >= 1.28
+        let list = "stable-x86_64-unknown-linux-gnu /home/u/.rustup/toolchains/stable\n\
+                    risc0 (active, default) /data/toolchains/rust\n";
+        assert_eq!(
+            parse_toolchain_path(list, "risc0"),
+            Some(PathBuf::from("/data/toolchains/rust"))
+        );
+        assert_eq!(
+            parse_toolchain_path(list, "stable-x86_64-unknown-linux-gnu"),
+            Some(PathBuf::from("/home/u/.rustup/toolchains/stable"))
+        );
+
+        assert_eq!(parse_toolchain_path(list, "nightly"), None);
+        assert_eq!(
+            parse_toolchain_path("no installed toolchains\n", "risc0"),
+            None
+        );
+    }
 }
@@ -116,7 +116,9 @@ impl Drop for FileLock {
 }
 
 pub fn flock(path: &Path) -> Result<FileLock> {
-    let parent = path.parent().unwrap();
+    let parent = path
+        .parent()
+        .with_context(|| format!("invalid lock file path `{}`", path.display()))?;
     std::fs::create_dir_all(parent)
         .context(format!("failed to create directory `{}`", parent.display()))?;
     let file = OpenOptions::new()
@@ -124,8 +126,67 @@ pub fn flock(path: &Path) -> Result<FileLock> {
         .read(true)
         .write(true)
         .open(path)?;
-    file.lock_exclusive()?;
-    return Ok(FileLock(file));
+    if let Err(err) = file.try_lock_exclusive() {
+        if err.kind() != fs2::lock_contended_error().kind() {
+            return Err(err.into());
+        }
+        eprintln!(
+            "Waiting for another cargo-risczero process to release `{}`...",
+            path.display()
+        );
+        file.lock_exclusive()?;
+    }
+    Ok(FileLock(file))
+}
+
+/// Format a size in bytes for display.
+pub fn format_size(bytes: u64) -> String {
+    format!("{:.1} MiB", bytes as f64 / (1024 * 1024) as f64)
+}
+
+/// Find the release tags closest to a requested version that does not exist,
+/// best match first.
+///
+/// Tags are compared by edit distance, ignoring a leading `v`. Tags that
+/// contain the requested version, e.g. `r0.1.79.0` for `1.79`, match best.
+pub fn suggest_versions<'a>(requested: &str, tags: &'a [String]) -> Vec<&'a str> {
+    let requested = requested.trim_start_matches('v');
+    let max_distance = (requested.len() / 3).max(2);
+    let mut candidates: Vec<_> = tags
+        .iter()
+        .filter_map(|tag| {
+            let version = tag.trim_start_matches('v');
+            let distance = if version.contains(requested) {
+                0
+            } else {
+                edit_distance(requested, version)
+            };
+            (distance <= max_distance).then_some((distance, tag.as_str()))
+        })
+        .collect();
+    // The sort is stable, so equally close tags keep their order.
+    candidates.sort_by_key(|(distance, _)| *distance);
+    candidates.into_iter().take(3).map(|(_, tag)| tag).collect()
+}
+
+/// The Levenshtein distance between `a` and `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, a) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, b) in b.iter().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = if a == *b {
+                diagonal
+            } else {
+                1 + diagonal.min(row[j]).min(above)
+            };
+            diagonal = above;
+        }
+    }
+    row[b.len()]
 }
 
 #[derive(Debug)]
@@ -197,3 +258,24 @@ pub(crate) fn get_client_envs(client_envs: &ClientEnvs) -> Result<(String, Strin
 
     Ok((api_url, api_key))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::suggest_versions;
+
+    #[test]
+    fn suggest_close_versions() {
+        let tags: Vec<_> = ["v1.1.0", "v1.0.5", "v1.0.1", "v0.21.0"]
+            .map(String::from)
+            .into();
+        assert_eq!(
+            suggest_versions("1.0.4", &tags),
+            vec!["v1.0.5", "v1.0.1", "v1.1.0"]
+        );
+        assert_eq!(suggest_versions("v0.21", &tags), vec!["v0.21.0"]);
+        assert!(suggest_versions("2024.01.05", &tags).is_empty());
+
+        let tags: Vec<_> = ["r0.1.79.0", "r0.1.78.0"].map(String::from).into();
+        assert_eq!(suggest_versions("1.79", &tags), vec!["r0.1.79.0"]);
+    }
+}